@@ -0,0 +1,249 @@
+use crate::monitor::File;
+use crate::review::{Command, CommandEnum};
+use crate::rules::host_matches;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// When a move is applied right away instead of waiting for review. Every condition
+/// that is set must hold; a policy without conditions applies nothing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AutoApply {
+    /// Reasons as shown in review, e.g. `course-code`, `learned` or `rule:receipts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_confidence: Option<f32>,
+    /// Hosts the file must have been downloaded from; subdomains count.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+}
+
+impl AutoApply {
+    pub fn allows(&self, command: &Command, file: &File) -> bool {
+        if command.command != CommandEnum::Move
+            || (self.reasons.is_empty() && self.min_confidence.is_none() && self.hosts.is_empty())
+        {
+            return false;
+        }
+        let reason_matches = self.reasons.is_empty()
+            || command
+                .reason
+                .as_ref()
+                .is_some_and(|reason| self.reasons.iter().any(|wanted| reason.matches(wanted)));
+        let confident = self.min_confidence.is_none_or(|min| {
+            command
+                .confidence
+                .is_some_and(|confidence| confidence >= min)
+        });
+        let host_allowed = self.hosts.is_empty()
+            || [&file.url, &file.referrer]
+                .into_iter()
+                .flatten()
+                .filter_map(|url| Url::parse(url).ok())
+                .filter_map(|url| url.host_str().map(str::to_string))
+                .any(|host| self.hosts.iter().any(|wanted| host_matches(&host, wanted)));
+        reason_matches && confident && host_allowed
+    }
+}
+
+/// Whether any of the policies lets the command be applied without review.
+pub fn should_auto_apply(policies: &[AutoApply], command: &Command, file: &File) -> bool {
+    policies.iter().any(|policy| policy.allows(command, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::review::ReasonEnum;
+    use std::path::PathBuf;
+
+    fn policy(reasons: &[&str], min_confidence: Option<f32>, hosts: &[&str]) -> AutoApply {
+        AutoApply {
+            reasons: reasons.iter().map(|r| r.to_string()).collect(),
+            min_confidence,
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    fn command(command: CommandEnum, reason: ReasonEnum, confidence: f32) -> Command {
+        Command {
+            file_path: Some(PathBuf::from("/home/user/Downloads/receipt.pdf")),
+            command,
+            destination: Some(PathBuf::from("/home/user/Receipts")),
+            reason: Some(reason),
+            rename: None,
+            confidence: Some(confidence),
+            tags: Vec::new(),
+        }
+    }
+
+    fn file(url: Option<&str>) -> File {
+        File {
+            name: "receipt.pdf".to_string(),
+            url: url.map(str::to_string),
+            referrer: None,
+            path: PathBuf::from("/home/user/Downloads/receipt.pdf"),
+            text: None,
+        }
+    }
+
+    #[test]
+    fn allows() {
+        let rule = || ReasonEnum::Rule("receipts".to_string());
+        let cases = [
+            (
+                policy(&[], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+            (
+                policy(&["rule"], None, &[]),
+                CommandEnum::Copy,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+            (
+                policy(&["rule"], None, &[]),
+                CommandEnum::Duplicate,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+            (
+                policy(&["rule"], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                true,
+            ),
+            (
+                policy(&["rule:receipts"], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                true,
+            ),
+            (
+                policy(&["rule:Receipts"], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                true,
+            ),
+            (
+                policy(&["rule:invoices"], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+            (
+                policy(&["course-code"], None, &[]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+            (
+                policy(&["course-code", "learned"], None, &[]),
+                CommandEnum::Move,
+                ReasonEnum::Learned,
+                0.5,
+                None,
+                true,
+            ),
+            (
+                policy(&[], Some(0.9), &[]),
+                CommandEnum::Move,
+                ReasonEnum::Learned,
+                0.9,
+                None,
+                true,
+            ),
+            (
+                policy(&[], Some(0.9), &[]),
+                CommandEnum::Move,
+                ReasonEnum::Learned,
+                0.89,
+                None,
+                false,
+            ),
+            (
+                policy(&["learned"], Some(0.9), &[]),
+                CommandEnum::Move,
+                ReasonEnum::Learned,
+                0.5,
+                None,
+                false,
+            ),
+            (
+                policy(&[], None, &["amazon.ca"]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                Some("https://amazon.ca/receipt.pdf"),
+                true,
+            ),
+            (
+                policy(&[], None, &["amazon.ca"]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                Some("https://www.amazon.ca/gp/receipt.pdf"),
+                true,
+            ),
+            (
+                policy(&[], None, &["amazon.ca"]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                Some("https://notamazon.ca/receipt.pdf"),
+                false,
+            ),
+            (
+                policy(&[], None, &["amazon.ca"]),
+                CommandEnum::Move,
+                rule(),
+                1.0,
+                None,
+                false,
+            ),
+        ];
+        for (policy, kind, reason, confidence, url, expected) in cases {
+            let command = command(kind, reason, confidence);
+            assert_eq!(
+                policy.allows(&command, &file(url)),
+                expected,
+                "{:?} {:?} {:?}",
+                policy,
+                command,
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn referrer_host_counts() {
+        let mut file = file(Some("https://cdn.example.com/receipt.pdf"));
+        file.referrer = Some("https://www.amazon.ca/orders".to_string());
+        let command = command(CommandEnum::Move, ReasonEnum::Learned, 1.0);
+        assert!(policy(&[], None, &["amazon.ca"]).allows(&command, &file));
+        assert!(!should_auto_apply(&[], &command, &file));
+        assert!(should_auto_apply(
+            &[policy(&[], None, &[]), policy(&[], None, &["amazon.ca"])],
+            &command,
+            &file
+        ));
+    }
+}
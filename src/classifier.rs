@@ -0,0 +1,429 @@
+use crate::llm::{ChatClient, ChatMessage, Role};
+use crate::matcher::{CourseMatch, CourseMatcher};
+use crate::model::{model_path, Features, Model};
+use crate::monitor::File;
+use crate::review::ReasonEnum;
+use crate::setup::Course;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub type ClassifyResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Where a classifier thinks a file belongs.
+#[derive(Debug, Clone)]
+pub enum Placement {
+    /// The course's directory for the current term.
+    Course(Course),
+    /// Any other directory.
+    Directory(PathBuf),
+}
+
+/// A classifier's verdict on where a file belongs.
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub placement: Placement,
+    pub reason: ReasonEnum,
+    /// How sure the classifier is, from 0.0 to 1.0.
+    pub confidence: f32,
+}
+
+/// One way of deciding which course a downloaded file belongs to.
+/// Returns `Ok(None)` when the classifier has no opinion about the file.
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    fn name(&self) -> &str;
+    async fn classify(&self, file: &File) -> ClassifyResult<Option<Classification>>;
+}
+
+/// Runs the classifiers in order and returns the first verdict.
+/// A classifier that fails is logged and skipped.
+pub async fn classify(classifiers: &[&dyn Classifier], file: &File) -> Option<Classification> {
+    for classifier in classifiers {
+        match classifier.classify(file).await {
+            Ok(Some(classification)) => return Some(classification),
+            Ok(None) => {}
+            Err(e) => log::error!(
+                "{} classifier failed on {}: {}",
+                classifier.name(),
+                file.name,
+                e
+            ),
+        }
+    }
+    None
+}
+
+/// Looks for a course's code, aliases, patterns or LEARN org unit in the file name and
+/// its origin URLs, or failing those its text.
+pub struct CourseCodeClassifier {
+    matcher: CourseMatcher,
+}
+
+impl CourseCodeClassifier {
+    pub fn new(courses: Vec<Course>) -> Result<CourseCodeClassifier, regex::Error> {
+        Ok(CourseCodeClassifier {
+            matcher: CourseMatcher::new(courses)?,
+        })
+    }
+
+    /// Every course the file could belong to, most specific evidence first.
+    pub fn candidates(&self, file: &File) -> Vec<CourseMatch> {
+        let urls: Vec<&str> = [&file.url, &file.referrer]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        self.matcher
+            .candidates(&file.name, &urls, file.text.as_deref())
+    }
+}
+
+#[async_trait]
+impl Classifier for CourseCodeClassifier {
+    fn name(&self) -> &str {
+        "course code"
+    }
+
+    async fn classify(&self, file: &File) -> ClassifyResult<Option<Classification>> {
+        let candidates = self.candidates(file);
+        if candidates.len() > 1 {
+            log::debug!(
+                "{} mentions several courses: {}",
+                file.name,
+                candidates
+                    .iter()
+                    .map(|m| format!("{} ({:?}, {:.2})", m.course.name, m.rule, m.score))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(candidates.into_iter().next().map(|m| Classification {
+            placement: Placement::Course(m.course),
+            reason: ReasonEnum::CourseCode,
+            confidence: m.score,
+        }))
+    }
+}
+
+/// Ranks destinations with the model trained by `nimbus train`. The model file is
+/// reloaded whenever it changes, so retraining needs no restart.
+pub struct ModelClassifier {
+    dir: PathBuf,
+    /// Course directories, so a learned course destination is reported as that course.
+    course_directories: HashMap<PathBuf, Course>,
+    cached: Mutex<(Option<SystemTime>, Option<Model>)>,
+}
+
+/// Below this the model's best guess is left to the next classifier.
+const MIN_MODEL_CONFIDENCE: f32 = 0.6;
+/// The model needs a few reviewed suggestions before its guesses mean anything.
+const MIN_MODEL_EXAMPLES: usize = 5;
+
+impl ModelClassifier {
+    pub fn new(dir: PathBuf, course_directories: HashMap<PathBuf, Course>) -> ModelClassifier {
+        ModelClassifier {
+            dir,
+            course_directories,
+            cached: Mutex::new((None, None)),
+        }
+    }
+
+    fn model(&self) -> io::Result<Option<Model>> {
+        let modified = match std::fs::metadata(model_path(&self.dir)) {
+            Ok(metadata) => Some(metadata.modified()?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if cached.0 != modified || (modified.is_some() && cached.1.is_none()) {
+            *cached = (modified, Model::load(&self.dir)?);
+        }
+        Ok(cached.1.clone())
+    }
+}
+
+#[async_trait]
+impl Classifier for ModelClassifier {
+    fn name(&self) -> &str {
+        "learned"
+    }
+
+    async fn classify(&self, file: &File) -> ClassifyResult<Option<Classification>> {
+        let Some(model) = self.model()? else {
+            return Ok(None);
+        };
+        if model.examples < MIN_MODEL_EXAMPLES {
+            return Ok(None);
+        }
+        let url = file.url.as_deref().or(file.referrer.as_deref());
+        let ranked = model.rank(&Features::extract(&file.name, url));
+        let Some((destination, confidence)) = ranked.into_iter().next() else {
+            return Ok(None);
+        };
+        if confidence < MIN_MODEL_CONFIDENCE {
+            return Ok(None);
+        }
+        let placement = match self.course_directories.get(&destination) {
+            Some(course) => Placement::Course(course.clone()),
+            None => Placement::Directory(destination),
+        };
+        Ok(Some(Classification {
+            placement,
+            reason: ReasonEnum::Learned,
+            confidence,
+        }))
+    }
+}
+
+/// Asks a chat model to pick a course from the configured list.
+pub struct LlmClassifier {
+    client: ChatClient,
+    courses: Vec<Course>,
+}
+
+/// The JSON object the model is asked to answer with.
+#[derive(Debug, Deserialize)]
+struct LlmAnswer {
+    course: Option<String>,
+    confidence: Option<f32>,
+}
+
+impl LlmClassifier {
+    pub fn new(client: ChatClient, courses: Vec<Course>) -> LlmClassifier {
+        LlmClassifier { client, courses }
+    }
+
+    fn describe_file(file: &File) -> String {
+        let mut description = format!(
+            "File name: {}\nDownloaded from: {}\nReferrer: {}",
+            file.name,
+            file.url.as_deref().unwrap_or("unknown"),
+            file.referrer.as_deref().unwrap_or("unknown"),
+        );
+        if let Some(text) = &file.text {
+            description.push_str(&format!("\nBeginning of the file's text:\n{}", text));
+        }
+        description
+    }
+}
+
+#[async_trait]
+impl Classifier for LlmClassifier {
+    fn name(&self) -> &str {
+        "LLM"
+    }
+
+    async fn classify(&self, file: &File) -> ClassifyResult<Option<Classification>> {
+        if self.courses.is_empty() {
+            return Ok(None);
+        }
+        let history = vec![
+            ChatMessage {
+                role: Role::System,
+                content: craft_starting_prompt(&self.courses),
+            },
+            ChatMessage {
+                role: Role::User,
+                content: LlmClassifier::describe_file(file),
+            },
+        ];
+        let reply = self.client.complete(&history).await?;
+        let answer = parse_answer(&reply)?;
+        let course = match answer.course {
+            Some(name) => find_course(&self.courses, &name),
+            None => None,
+        };
+        Ok(course.map(|course| Classification {
+            placement: Placement::Course(course),
+            reason: ReasonEnum::Chatgpt,
+            confidence: answer.confidence.unwrap_or(0.5).clamp(0.0, 1.0),
+        }))
+    }
+}
+
+fn craft_starting_prompt(courses: &[Course]) -> String {
+    let course_list: Vec<String> = courses
+        .iter()
+        .map(|course| {
+            if course.aliases.is_empty() {
+                format!("- {}: {}", course.name, course.description)
+            } else {
+                format!(
+                    "- {} (also known as {}): {}",
+                    course.name,
+                    course.aliases.join(", "),
+                    course.description
+                )
+            }
+        })
+        .collect();
+    format!("
+    Your job is to determine whether a downloaded file corresponds to one of the following courses:
+    {}
+    You will be given the URL of where the file was downloaded from, the name of the file and, if available, the beginning of its text.
+    If the URL contains 'learn.uwaterloo.ca', it is highly likely but not guaranteed that the file is a course file.
+    You should check to see if the file name or the URL contains the course code or the course name.
+    Answer with only a JSON object of the form {{\"course\": \"<course code>\", \"confidence\": <number from 0 to 1>}}.
+    If the file does not belong to any of the courses, use null for the course.", course_list.join("\n    "))
+}
+
+/// Pulls the JSON object out of the model's reply, tolerating surrounding prose or code fences.
+fn parse_answer(reply: &str) -> ClassifyResult<LlmAnswer> {
+    let start = reply.find('{').ok_or("No JSON object in LLM reply")?;
+    let end = reply.rfind('}').ok_or("No JSON object in LLM reply")?;
+    if end < start {
+        return Err("No JSON object in LLM reply".into());
+    }
+    Ok(serde_json::from_str(&reply[start..=end])?)
+}
+
+fn find_course(courses: &[Course], name: &str) -> Option<Course> {
+    let wanted: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+    if wanted.eq_ignore_ascii_case("NONE") {
+        return None;
+    }
+    let same = |name: &String| {
+        let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        name.eq_ignore_ascii_case(&wanted)
+    };
+    courses
+        .iter()
+        .find(|course| same(&course.name) || course.aliases.iter().any(same))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decisions::{Action, Decision};
+    use crate::testing::temp_dir;
+    use chrono::Local;
+
+    fn file(name: &str) -> File {
+        File {
+            name: name.to_string(),
+            url: None,
+            referrer: None,
+            path: PathBuf::from("/dl").join(name),
+            text: None,
+        }
+    }
+
+    fn accepted(queue_id: u64, name: &str, destination: &str) -> Decision {
+        Decision {
+            queue_id,
+            decided_at: Local::now(),
+            action: Action::Accept,
+            file_name: Some(name.to_string()),
+            source_dir: Some(PathBuf::from("/dl")),
+            suggested_destination: Some(PathBuf::from(destination)),
+            chosen_destination: Some(PathBuf::from(destination)),
+            course: None,
+            new_name: None,
+            features: Some(Features::extract(name, None)),
+        }
+    }
+
+    /// A classifier whose saved model was trained on `decisions`.
+    fn model_classifier(name: &str, decisions: &[Decision]) -> ModelClassifier {
+        let dir = temp_dir(&format!("classifier-{}", name));
+        Model::train(decisions).save(&dir).unwrap();
+        ModelClassifier::new(dir, HashMap::new())
+    }
+
+    async fn learned(classifier: &ModelClassifier, name: &str) -> Option<(PathBuf, f32)> {
+        let classification = classifier.classify(&file(name)).await.unwrap()?;
+        match classification.placement {
+            Placement::Directory(directory) => Some((directory, classification.confidence)),
+            Placement::Course(course) => panic!("unexpected course {}", course.name),
+        }
+    }
+
+    fn receipts_and_resumes(count: u64) -> Vec<Decision> {
+        (0..count)
+            .map(|n| match n % 2 {
+                0 => accepted(n, &format!("receipt {}.pdf", n), "/base/Finance"),
+                _ => accepted(n, &format!("resume {}.docx", n), "/base/Career"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn model_needs_enough_examples() {
+        let few = model_classifier("few", &receipts_and_resumes(MIN_MODEL_EXAMPLES as u64 - 1));
+        assert_eq!(learned(&few, "receipt 99.pdf").await, None);
+
+        let enough = model_classifier("enough", &receipts_and_resumes(MIN_MODEL_EXAMPLES as u64));
+        let (destination, confidence) = learned(&enough, "receipt 99.pdf").await.unwrap();
+        assert_eq!(destination, PathBuf::from("/base/Finance"));
+        assert!(confidence >= MIN_MODEL_CONFIDENCE);
+    }
+
+    #[tokio::test]
+    async fn model_leaves_unsure_guesses_to_the_next_classifier() {
+        let classifier = model_classifier("unsure", &receipts_and_resumes(6));
+        // No word it has seen, so it is split evenly between both destinations.
+        assert_eq!(learned(&classifier, "holiday photo.jpg").await, None);
+    }
+
+    #[test]
+    fn parses_answers_wrapped_in_prose() {
+        let cases = [
+            (r#"{"course": "CS246", "confidence": 0.9}"#, Some("CS246"), Some(0.9)),
+            (
+                "Sure! Here is my answer:\n```json\n{\"course\": \"CS246\", \"confidence\": 0.8}\n```\nLet me know if you need anything else.",
+                Some("CS246"),
+                Some(0.8),
+            ),
+            (r#"The file is {"course": null, "confidence": 0.95}."#, None, Some(0.95)),
+            (r#"{"course": "MATH239"}"#, Some("MATH239"), None),
+        ];
+        for (reply, course, confidence) in cases {
+            let answer = parse_answer(reply).unwrap();
+            assert_eq!(answer.course.as_deref(), course, "{}", reply);
+            assert_eq!(answer.confidence, confidence, "{}", reply);
+        }
+        for reply in ["CS246", "} {", "{not json}", ""] {
+            assert!(parse_answer(reply).is_err(), "{}", reply);
+        }
+    }
+
+    #[test]
+    fn finds_the_course_the_answer_names() {
+        let courses = vec![
+            Course {
+                name: "CS246".to_string(),
+                ..Default::default()
+            },
+            Course {
+                name: "CO250".to_string(),
+                aliases: vec!["CO 250".to_string(), "C&O 250".to_string()],
+                ..Default::default()
+            },
+        ];
+        let cases = [
+            ("CS246", Some("CS246")),
+            ("cs 246", Some("CS246")),
+            ("C&O250", Some("CO250")),
+            ("co250", Some("CO250")),
+            ("MATH239", None),
+            ("none", None),
+            ("", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                find_course(&courses, name)
+                    .map(|course| course.name)
+                    .as_deref(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+}
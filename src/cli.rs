@@ -1,21 +1,111 @@
-use clap::{Parser, Subcommand};
-use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 extern crate clap;
 #[derive(Debug, serde::Deserialize, Subcommand)]
 pub enum Commands {
     //Configures nimbus
     Config,
     //Starts a reveiw
-    Review,
+    Review {
+        #[clap(subcommand)]
+        action: Option<ReviewCommands>,
+    },
     //Starts the daemon
     Start,
+    /// Shows what the running daemon is doing
+    Status {
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Stops suggesting destinations until `nimbus resume`
+    Pause,
+    /// Resumes a paused daemon
+    Resume,
+    /// Makes the running daemon re-read its config
+    Reload,
+    /// Stops the running daemon
+    Stop,
+    /// Moves files back to where they were before nimbus moved them
+    Undo {
+        /// Journal id of the operation to undo (see `nimbus history`)
+        #[clap(conflicts_with = "last")]
+        id: Option<u64>,
+        /// Undo the last N operations
+        #[clap(long, value_name = "N", default_value_t = 1)]
+        last: usize,
+    },
+    /// Lists the file operations nimbus has applied
+    History {
+        /// How many operations to show, most recent first
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+    /// Suggests destinations for files already in the downloads folder
+    Scan {
+        /// Only look at files modified on or after this date (YYYY-MM-DD)
+        #[clap(long, value_name = "DATE")]
+        since: Option<NaiveDate>,
+    },
+    /// Retrains the learned model from past review decisions
+    Train,
+    /// Inspects the learned model
+    Model {
+        #[clap(subcommand)]
+        action: ModelCommands,
+    },
+}
+
+#[derive(Debug, serde::Deserialize, Subcommand)]
+pub enum ModelCommands {
+    /// Shows what the model has learned per destination
+    Stats {
+        /// Print the whole model as JSON
+        #[clap(long)]
+        json: bool,
+    },
 }
 
 #[derive(Parser)]
 pub struct Nimbus {
+    /// Path to config.yaml. Defaults to $NIMBUS_CONFIG, then the user config directory
+    #[clap(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
     #[clap(subcommand)]
     pub command: Commands,
 }
+
+#[derive(Debug, serde::Deserialize, Subcommand)]
+pub enum ReviewCommands {
+    /// Lists queued suggestions
+    List {
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+        /// Include suggestions that were already accepted or rejected
+        #[clap(long)]
+        all: bool,
+    },
+    /// Accepts pending suggestions and applies them
+    Accept(Selection),
+    /// Rejects pending suggestions
+    Reject(Selection),
+}
+
+#[derive(Debug, serde::Deserialize, Args)]
+pub struct Selection {
+    /// Suggestion ids, or globs matched against the file path
+    #[clap(required_unless_present = "all")]
+    pub targets: Vec<String>,
+    /// Select every pending suggestion
+    #[clap(long)]
+    pub all: bool,
+    /// Only select suggestions with this reason (course-code, chatgpt, learned, duplicate, rule
+    /// or rule:<name>)
+    #[clap(long, value_name = "REASON", value_parser = crate::review::ReasonEnum::parse_filter)]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,317 @@
+use chrono::{DateTime, Local};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const SOCKET_FILE_NAME: &str = "nimbus.sock";
+const PID_FILE_NAME: &str = "nimbus.pid";
+/// How many error messages `nimbus status` shows.
+const RECENT_ERRORS: usize = 10;
+
+/// A request to the running daemon, sent as one JSON line over its socket.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Status,
+    /// Stop making suggestions until resumed.
+    Pause,
+    /// Make suggestions again, catching up on files that arrived while paused.
+    Resume,
+    /// Re-read the config.
+    Reload,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DaemonStatus>,
+}
+
+impl Response {
+    pub fn done(message: &str) -> Response {
+        Response {
+            ok: true,
+            message: Some(message.to_string()),
+            status: None,
+        }
+    }
+
+    pub fn failed(message: String) -> Response {
+        Response {
+            ok: false,
+            message: Some(message),
+            status: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: DateTime<Local>,
+    pub paused: bool,
+    pub roots: Vec<PathBuf>,
+    /// Suggestions waiting for review.
+    pub pending: usize,
+    /// Files waiting to finish downloading.
+    pub settling: usize,
+    pub recent_errors: Vec<LoggedError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedError {
+    pub at: DateTime<Local>,
+    pub message: String,
+}
+
+pub fn socket_path(dir: &Path) -> PathBuf {
+    dir.join(SOCKET_FILE_NAME)
+}
+
+/// Held by the running daemon. Another daemon cannot take the lock on the PID file, and
+/// the socket is removed and the PID file emptied when this is dropped.
+pub struct DaemonLock {
+    pid_file: File,
+    socket_path: PathBuf,
+}
+
+impl DaemonLock {
+    /// Fails if another daemon holds the lock.
+    pub fn acquire(dir: &Path) -> Result<DaemonLock, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let pid_path = dir.join(PID_FILE_NAME);
+        let mut pid_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&pid_path)?;
+        if pid_file.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            pid_file.read_to_string(&mut pid)?;
+            return Err(format!(
+                "nimbus is already running (pid {}). Stop it with `nimbus stop`",
+                pid.trim()
+            )
+            .into());
+        }
+        pid_file.set_len(0)?;
+        pid_file.rewind()?;
+        writeln!(pid_file, "{}", std::process::id())?;
+        pid_file.sync_data()?;
+        let socket_path = socket_path(dir);
+        // Left behind by a daemon that did not shut down cleanly.
+        match fs::remove_file(&socket_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        Ok(DaemonLock {
+            pid_file,
+            socket_path,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+}
+
+impl Drop for DaemonLock {
+    // Everything is cleaned up while the lock is still held, so none of it can belong to
+    // a newer daemon yet. The PID file itself stays: were it removed, a newer daemon could
+    // lock a fresh file while a third one still sees the old one locked.
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+        let _ = self.pid_file.set_len(0);
+        let _ = self.pid_file.unlock();
+    }
+}
+
+/// Sends a request to the running daemon and waits for its answer.
+pub fn send(dir: &Path, request: Request) -> Result<Response, Box<dyn std::error::Error>> {
+    let path = socket_path(dir);
+    let mut stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            format!("nimbus is not running (no daemon at {})", path.display())
+        }
+        _ => format!("Could not reach nimbus at {}: {}", path.display(), e),
+    })?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Wraps the real logger and keeps the last few errors for `nimbus status`.
+pub struct ErrorRecorder<L> {
+    inner: L,
+}
+
+fn recorded() -> &'static Mutex<VecDeque<LoggedError>> {
+    static RECORDED: OnceLock<Mutex<VecDeque<LoggedError>>> = OnceLock::new();
+    RECORDED.get_or_init(|| Mutex::new(VecDeque::with_capacity(RECENT_ERRORS)))
+}
+
+impl<L: log::Log> ErrorRecorder<L> {
+    pub fn new(inner: L) -> ErrorRecorder<L> {
+        ErrorRecorder { inner }
+    }
+}
+
+impl<L: log::Log> log::Log for ErrorRecorder<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() == log::Level::Error {
+            let mut errors = recorded().lock().unwrap();
+            if errors.len() == RECENT_ERRORS {
+                errors.pop_front();
+            }
+            errors.push_back(LoggedError {
+                at: Local::now(),
+                message: record.args().to_string(),
+            });
+        }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// The errors logged most recently, oldest first.
+pub fn recent_errors() -> Vec<LoggedError> {
+    recorded().lock().unwrap().iter().cloned().collect()
+}
+
+/// Prints the daemon's status, for `nimbus status`.
+pub fn show_status(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let response = send(&crate::setup::data_dir()?, Request::Status)?;
+    let status = match response.status {
+        Some(status) if response.ok => status,
+        _ => return Err(response.message.unwrap_or_default().into()),
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    let uptime = Local::now() - status.started_at;
+    println!(
+        "nimbus is {} (pid {}), up {}h {}m since {}",
+        if status.paused { "paused" } else { "running" },
+        status.pid,
+        uptime.num_hours(),
+        uptime.num_minutes() % 60,
+        status.started_at.format("%Y-%m-%d %H:%M")
+    );
+    println!("Watching:");
+    for root in &status.roots {
+        println!("  {}", root.display());
+    }
+    println!("Pending suggestions: {}", status.pending);
+    println!("Files still downloading: {}", status.settling);
+    if status.recent_errors.is_empty() {
+        println!("No recent errors");
+    } else {
+        println!("Recent errors:");
+        for error in &status.recent_errors {
+            println!(
+                "  {}  {}",
+                error.at.format("%Y-%m-%d %H:%M:%S"),
+                error.message
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Sends a pause, resume, reload or stop request and prints the answer.
+pub fn control(request: Request) -> Result<(), Box<dyn std::error::Error>> {
+    let response = send(&crate::setup::data_dir()?, request)?;
+    let message = response.message.unwrap_or_default();
+    if !response.ok {
+        return Err(message.into());
+    }
+    println!("{}", message);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn second_daemon_is_refused() {
+        let dir = temp_dir("control-lock");
+        let lock = DaemonLock::acquire(&dir).unwrap();
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            fs::read_to_string(dir.join(PID_FILE_NAME)).unwrap().trim(),
+            pid
+        );
+
+        let error = DaemonLock::acquire(&dir).err().unwrap().to_string();
+        assert!(error.contains("already running"), "{}", error);
+        assert!(error.contains(&pid), "{}", error);
+
+        drop(lock);
+        let _lock = DaemonLock::acquire(&dir).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_lock_removes_the_socket_but_keeps_the_pid_file() {
+        let dir = temp_dir("control-drop");
+        let lock = DaemonLock::acquire(&dir).unwrap();
+        let _listener = UnixListener::bind(lock.socket_path()).unwrap();
+        drop(lock);
+
+        assert!(!socket_path(&dir).exists());
+        assert_eq!(fs::read_to_string(dir.join(PID_FILE_NAME)).unwrap(), "");
+    }
+
+    #[test]
+    fn stale_socket_is_replaced() {
+        let dir = temp_dir("control-stale");
+        let listener = UnixListener::bind(socket_path(&dir)).unwrap();
+        drop(listener);
+        let lock = DaemonLock::acquire(&dir).unwrap();
+        assert!(!lock.socket_path().exists());
+    }
+
+    #[test]
+    fn send_without_a_daemon_says_it_is_not_running() {
+        let dir = temp_dir("control-not-running");
+        let error = send(&dir, Request::Status).err().unwrap().to_string();
+        assert!(error.contains("not running"), "{}", error);
+    }
+
+    #[test]
+    fn requests_are_json_lines() {
+        let cases = [
+            (Request::Status, r#"{"command":"status"}"#),
+            (Request::Pause, r#"{"command":"pause"}"#),
+            (Request::Resume, r#"{"command":"resume"}"#),
+            (Request::Reload, r#"{"command":"reload"}"#),
+            (Request::Stop, r#"{"command":"stop"}"#),
+        ];
+        for (request, json) in cases {
+            assert_eq!(serde_json::to_string(&request).unwrap(), json);
+        }
+    }
+}
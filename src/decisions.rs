@@ -0,0 +1,116 @@
+use crate::model::Features;
+use crate::setup::data_dir;
+use crate::store::JsonLines;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What the reviewer did with a suggestion.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    ChangeCourse,
+    CustomDestination,
+    Rename,
+    Accept,
+    Reject,
+}
+
+/// A reviewer's verdict on or change to a suggestion, kept so classifiers can learn from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub queue_id: u64,
+    pub decided_at: DateTime<Local>,
+    pub action: Action,
+    pub file_name: Option<String>,
+    /// The folder the file was in when the decision was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_dir: Option<PathBuf>,
+    pub suggested_destination: Option<PathBuf>,
+    pub chosen_destination: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub course: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_name: Option<String>,
+    /// What the learned model saw of the file when the decision was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Features>,
+}
+
+/// Append-only JSON-lines log of review decisions under the data dir.
+pub struct DecisionLog {
+    lines: JsonLines<Decision>,
+}
+
+impl DecisionLog {
+    pub fn open(dir: &Path) -> io::Result<DecisionLog> {
+        Ok(DecisionLog {
+            lines: JsonLines::open(dir, "decisions")?,
+        })
+    }
+
+    pub fn open_default() -> io::Result<DecisionLog> {
+        DecisionLog::open(&data_dir()?)
+    }
+
+    pub fn record(&self, decision: &Decision) -> io::Result<()> {
+        self.lines.append(decision)
+    }
+
+    pub fn all(&self) -> io::Result<Vec<Decision>> {
+        self.lines.all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn decision(queue_id: u64, action: Action) -> Decision {
+        Decision {
+            queue_id,
+            decided_at: Local::now(),
+            action,
+            file_name: Some("CS246_A1.pdf".to_string()),
+            source_dir: Some(PathBuf::from("/home/user/Downloads")),
+            suggested_destination: Some(PathBuf::from("/School/CS246")),
+            chosen_destination: Some(PathBuf::from("/School/CS246")),
+            course: None,
+            new_name: None,
+            features: None,
+        }
+    }
+
+    #[test]
+    fn keeps_every_decision_in_order() {
+        let dir = temp_dir("decisions-order");
+        let log = DecisionLog::open(&dir).unwrap();
+        assert!(log.all().unwrap().is_empty());
+
+        log.record(&decision(1, Action::Rename)).unwrap();
+        log.record(&decision(1, Action::Accept)).unwrap();
+        std::fs::write(
+            dir.join("decisions.jsonl"),
+            std::fs::read_to_string(dir.join("decisions.jsonl")).unwrap() + "not json\n",
+        )
+        .unwrap();
+        log.record(&decision(2, Action::Reject)).unwrap();
+
+        let actions: Vec<(u64, Action)> = log
+            .all()
+            .unwrap()
+            .iter()
+            .map(|decision| (decision.queue_id, decision.action))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                (1, Action::Rename),
+                (1, Action::Accept),
+                (2, Action::Reject)
+            ]
+        );
+    }
+}
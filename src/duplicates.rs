@@ -0,0 +1,305 @@
+use crate::journal::hash_file;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const INDEX_FILE_NAME: &str = "content-index.json";
+const LOCK_FILE_NAME: &str = "content-index.lock";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct IndexedFile {
+    size: u64,
+    modified: Option<SystemTime>,
+    /// BLAKE3 hash of the contents.
+    hash: String,
+}
+
+/// Content hashes of every file in the organized tree, so a download that is already
+/// filed can be recognized.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ContentIndex {
+    files: BTreeMap<PathBuf, IndexedFile>,
+    /// Modification time of the index file when this process last read or wrote it, to
+    /// notice files `nimbus review` filed in the meantime.
+    #[serde(skip)]
+    synced: Option<SystemTime>,
+}
+
+impl ContentIndex {
+    /// A missing or unreadable index is rebuilt from scratch on the next refresh.
+    pub fn load(dir: &Path) -> ContentIndex {
+        let contents = match fs::read_to_string(index_path(dir)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return ContentIndex::default(),
+            Err(e) => {
+                log::warn!("Could not read the content index: {}", e);
+                return ContentIndex::default();
+            }
+        };
+        let mut index: ContentIndex = serde_json::from_str(&contents).unwrap_or_else(|e| {
+            log::warn!("Rebuilding the content index: {}", e);
+            ContentIndex::default()
+        });
+        index.synced = modified(&index_path(dir));
+        index
+    }
+
+    /// Writes the index under the same lock `update` takes, so the daemon and
+    /// `nimbus review` never write over each other's temporary file.
+    pub fn save(&mut self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let _lock = lock(dir)?;
+        self.write(dir)
+    }
+
+    /// Applies `change` to the latest saved index and saves it, holding the lock from
+    /// reading to writing so a change another process saved meanwhile is not lost.
+    pub fn update<F>(&mut self, dir: &Path, change: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut ContentIndex) -> io::Result<()>,
+    {
+        fs::create_dir_all(dir)?;
+        let _lock = lock(dir)?;
+        self.reload_if_changed(dir);
+        change(self)?;
+        self.write(dir)
+    }
+
+    fn write(&mut self, dir: &Path) -> io::Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", INDEX_FILE_NAME));
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(tmp_path, index_path(dir))?;
+        self.synced = modified(&index_path(dir));
+        Ok(())
+    }
+
+    /// Reads the index again if another process saved it since this one last did.
+    pub fn reload_if_changed(&mut self, dir: &Path) {
+        if modified(&index_path(dir)) != self.synced {
+            *self = ContentIndex::load(dir);
+        }
+    }
+
+    /// Indexes a single file whose hash is already known, e.g. one that was just filed.
+    pub fn insert(&mut self, path: &Path, hash: String) -> io::Result<()> {
+        let metadata = fs::metadata(path)?;
+        let indexed = IndexedFile {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            hash,
+        };
+        self.files.insert(path.to_path_buf(), indexed);
+        Ok(())
+    }
+
+    /// Brings the index in line with the files under `root`, skipping hidden entries and
+    /// anything under `exclude`. Only files whose size or modification time changed are
+    /// hashed again. Returns whether the index changed.
+    pub fn refresh(&mut self, root: &Path, exclude: &[PathBuf]) -> io::Result<bool> {
+        let mut seen = BTreeMap::new();
+        let mut rehashed = false;
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(directory) = pending.pop() {
+            let entries = match fs::read_dir(&directory) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    log::warn!("Could not index {}: {}", directory.display(), e);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.')
+                    || exclude.iter().any(|excluded| path.starts_with(excluded))
+                {
+                    continue;
+                }
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if !file_type.is_file() {
+                    continue;
+                }
+                let metadata = entry.metadata()?;
+                let (size, modified) = (metadata.len(), metadata.modified().ok());
+                let indexed = match self.files.remove(&path) {
+                    Some(indexed) if indexed.size == size && indexed.modified == modified => {
+                        indexed
+                    }
+                    _ => match hash_file(&path) {
+                        Ok(hash) => {
+                            rehashed = true;
+                            IndexedFile {
+                                size,
+                                modified,
+                                hash,
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("Could not hash {}: {}", path.display(), e);
+                            continue;
+                        }
+                    },
+                };
+                seen.insert(path, indexed);
+            }
+        }
+        // Whatever is left was not seen again, so it was removed.
+        let changed = rehashed || !self.files.is_empty();
+        self.files = seen;
+        Ok(changed)
+    }
+
+    /// A filed copy with exactly these contents, other than `except` itself. Empty files
+    /// are never duplicates of each other. Files moved away since the last refresh, e.g.
+    /// by `nimbus undo`, are skipped.
+    pub fn find_hash(&self, hash: &str, except: &Path) -> Option<&Path> {
+        self.files
+            .iter()
+            .find(|(path, indexed)| {
+                indexed.size > 0
+                    && indexed.hash == hash
+                    && path.as_path() != except
+                    && path.exists()
+            })
+            .map(|(path, _)| path.as_path())
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path) && path.exists()
+    }
+}
+
+fn index_path(dir: &Path) -> PathBuf {
+    dir.join(INDEX_FILE_NAME)
+}
+
+// The lock is released when the returned handle is dropped.
+fn lock(dir: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE_NAME))?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Adds a file that was just filed to the index saved in `dir`, for processes that do
+/// not keep the index in memory.
+pub fn index_filed(dir: &Path, path: &Path) -> io::Result<()> {
+    let hash = hash_file(path)?;
+    ContentIndex::load(dir).update(dir, |index| index.insert(path, hash))
+}
+
+/// `notes (v2).pdf`, `notes (v3).pdf`, ...: the first version of `file_name` that is
+/// free in `directory`.
+pub fn versioned_name(directory: &Path, file_name: &str) -> String {
+    let path = Path::new(file_name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|version| format!("{} (v{}){}", stem, version, extension))
+        .find(|candidate| !directory.join(candidate).exists())
+        .expect("ran out of versions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn refresh_indexes_files_but_not_hidden_or_excluded_ones() {
+        let dir = temp_dir("duplicates-refresh");
+        let tree = dir.join("School");
+        fs::create_dir_all(tree.join("CS246")).unwrap();
+        fs::create_dir_all(tree.join("Inbox")).unwrap();
+        fs::write(tree.join("CS246").join("A1.pdf"), "assignment").unwrap();
+        fs::write(tree.join(".DS_Store"), "assignment").unwrap();
+        fs::write(tree.join("Inbox").join("A1.pdf"), "assignment").unwrap();
+
+        let mut index = ContentIndex::default();
+        assert!(index.refresh(&tree, &[tree.join("Inbox")]).unwrap());
+        assert!(!index.refresh(&tree, &[tree.join("Inbox")]).unwrap());
+
+        let hash = hash_file(&tree.join("CS246").join("A1.pdf")).unwrap();
+        let download = dir.join("A1.pdf");
+        assert_eq!(
+            index.find_hash(&hash, &download),
+            Some(tree.join("CS246").join("A1.pdf").as_path())
+        );
+        assert!(!index.contains(&tree.join(".DS_Store")));
+        assert!(!index.contains(&tree.join("Inbox").join("A1.pdf")));
+
+        fs::remove_file(tree.join("CS246").join("A1.pdf")).unwrap();
+        assert!(index.refresh(&tree, &[tree.join("Inbox")]).unwrap());
+        assert_eq!(index.find_hash(&hash, &download), None);
+    }
+
+    #[test]
+    fn find_hash_skips_empty_files_and_the_file_itself() {
+        let dir = temp_dir("duplicates-find");
+        let (empty, notes) = (dir.join("empty.txt"), dir.join("notes.txt"));
+        fs::write(&empty, "").unwrap();
+        fs::write(&notes, "notes").unwrap();
+        let mut index = ContentIndex::default();
+        index.insert(&empty, hash_file(&empty).unwrap()).unwrap();
+        index.insert(&notes, hash_file(&notes).unwrap()).unwrap();
+
+        assert_eq!(
+            index.find_hash(&hash_file(&empty).unwrap(), &dir.join("x")),
+            None
+        );
+        assert_eq!(index.find_hash(&hash_file(&notes).unwrap(), &notes), None);
+    }
+
+    #[test]
+    fn update_keeps_what_another_process_saved() {
+        let dir = temp_dir("duplicates-update");
+        let (first, second) = (dir.join("first.txt"), dir.join("second.txt"));
+        fs::write(&first, "first").unwrap();
+        fs::write(&second, "second").unwrap();
+        let mut daemon = ContentIndex::load(&dir);
+        daemon.save(&dir).unwrap();
+
+        index_filed(&dir, &first).unwrap();
+        daemon
+            .update(&dir, |index| index.insert(&second, hash_file(&second)?))
+            .unwrap();
+
+        let saved = ContentIndex::load(&dir);
+        assert!(saved.contains(&first));
+        assert!(saved.contains(&second));
+    }
+
+    #[test]
+    fn versioned_name_takes_the_first_free_version() {
+        let dir = temp_dir("duplicates-versioned");
+        assert_eq!(versioned_name(&dir, "notes.pdf"), "notes (v2).pdf");
+        fs::write(dir.join("notes (v2).pdf"), "").unwrap();
+        assert_eq!(versioned_name(&dir, "notes.pdf"), "notes (v3).pdf");
+        assert_eq!(versioned_name(&dir, "README"), "README (v2)");
+    }
+}
@@ -0,0 +1,507 @@
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What to do when a file already exists at the destination.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    /// Keep both, adding ` (1)`, ` (2)`, ... to the incoming file's name.
+    #[default]
+    Suffix,
+    Overwrite,
+    /// Leave both files where they are.
+    Skip,
+    /// Overwrite only if the incoming file was modified more recently.
+    KeepNewer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The file (or for a copy, the new copy) now lives at this path.
+    Moved(PathBuf),
+    /// Nothing was moved because of the file already at this path.
+    Skipped(PathBuf),
+}
+
+/// Moves files into place, creating missing directories and applying a collision policy.
+/// Falls back to copy, fsync and delete when the destination is on another filesystem.
+pub struct MoveExecutor {
+    policy: CollisionPolicy,
+}
+
+/// Where a file should go, and whether whatever is there may be replaced.
+struct Target {
+    path: PathBuf,
+    replace: bool,
+}
+
+impl MoveExecutor {
+    pub fn new(policy: CollisionPolicy) -> MoveExecutor {
+        MoveExecutor { policy }
+    }
+
+    /// Moves `source` to `target`, where `target` is the full destination file path.
+    /// Unless the policy says to replace it, a file that shows up at the target after it
+    /// was picked is never overwritten: the target is picked again instead.
+    pub fn move_file(&self, source: &Path, target: &Path) -> io::Result<MoveOutcome> {
+        let source_metadata = fs::metadata(source)?;
+        loop {
+            let target = match self.resolve_target(source, target, &source_metadata)? {
+                Ok(target) => target,
+                Err(skipped) => return Ok(skipped),
+            };
+            let renamed = if target.replace {
+                fs::rename(source, &target.path)
+            } else {
+                rename_no_replace(source, &target.path)
+            };
+            let placed = match renamed {
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    copy_across_devices(source, &target, &source_metadata)
+                }
+                placed => placed,
+            };
+            match placed {
+                Ok(()) => return Ok(MoveOutcome::Moved(target.path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Copies `source` to `target`, leaving `source` in place. `Moved` holds the copy's path.
+    pub fn copy_file(&self, source: &Path, target: &Path) -> io::Result<MoveOutcome> {
+        let source_metadata = fs::metadata(source)?;
+        loop {
+            let target = match self.resolve_target(source, target, &source_metadata)? {
+                Ok(target) => target,
+                Err(skipped) => return Ok(skipped),
+            };
+            if target.path.exists() && is_same_file(source, &target.path)? {
+                return Ok(MoveOutcome::Skipped(target.path));
+            }
+            match copy_to(source, &target, &source_metadata) {
+                Ok(()) => return Ok(MoveOutcome::Moved(target.path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Creates the target's directory and applies the collision policy.
+    /// Returns `Err` with the outcome to report when the policy says to skip.
+    fn resolve_target(
+        &self,
+        source: &Path,
+        target: &Path,
+        source_metadata: &fs::Metadata,
+    ) -> io::Result<Result<Target, MoveOutcome>> {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let new = |path: PathBuf| {
+            Ok(Target {
+                path,
+                replace: false,
+            })
+        };
+        let replace = Ok(Target {
+            path: target.to_path_buf(),
+            replace: true,
+        });
+        let skipped = Err(MoveOutcome::Skipped(target.to_path_buf()));
+        Ok(match fs::metadata(target) {
+            Ok(existing) if !is_same_file(source, target)? => match self.policy {
+                CollisionPolicy::Suffix => new(free_path(target)),
+                CollisionPolicy::Overwrite => replace,
+                CollisionPolicy::Skip => skipped,
+                CollisionPolicy::KeepNewer => {
+                    if source_metadata.modified()? > existing.modified()? {
+                        replace
+                    } else {
+                        skipped
+                    }
+                }
+            },
+            // Already there, e.g. a hard link to the same file.
+            Ok(_) => replace,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => new(target.to_path_buf()),
+            Err(e) => return Err(e),
+        })
+    }
+}
+
+/// Moves a file into the user's trash: `~/.Trash` on macOS, the freedesktop trash
+/// (`~/.local/share/Trash`) elsewhere. Returns the file's path inside the trash.
+pub fn trash_file(source: &Path) -> io::Result<PathBuf> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "Could not locate the trash");
+    let executor = MoveExecutor::new(CollisionPolicy::Suffix);
+    let file_name = source.file_name().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Invalid source",
+    ))?;
+    if cfg!(target_os = "macos") {
+        let trash = dirs::home_dir().ok_or_else(not_found)?.join(".Trash");
+        return match executor.move_file(source, &trash.join(file_name))? {
+            MoveOutcome::Moved(path) | MoveOutcome::Skipped(path) => Ok(path),
+        };
+    }
+
+    let trash = dirs::data_dir().ok_or_else(not_found)?.join("Trash");
+    let original = fs::canonicalize(source)?;
+    let trashed = match executor.move_file(source, &trash.join("files").join(file_name))? {
+        MoveOutcome::Moved(path) | MoveOutcome::Skipped(path) => path,
+    };
+    // File managers need the .trashinfo to show where the file came from and restore it.
+    let info_dir = trash.join("info");
+    fs::create_dir_all(&info_dir)?;
+    let info_name = format!(
+        "{}.trashinfo",
+        trashed.file_name().unwrap_or_default().to_string_lossy()
+    );
+    fs::write(
+        info_dir.join(info_name),
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            escape_trash_path(&original),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        ),
+    )?;
+    Ok(trashed)
+}
+
+/// Removes the `.trashinfo` for a file that has been taken back out of the freedesktop trash.
+pub fn forget_trash_info(trashed: &Path) {
+    let (Some(files), Some(name)) = (trashed.parent(), trashed.file_name()) else {
+        return;
+    };
+    if files.file_name().is_some_and(|n| n == "files") {
+        if let Some(trash) = files.parent() {
+            let info = trash
+                .join("info")
+                .join(format!("{}.trashinfo", name.to_string_lossy()));
+            let _ = fs::remove_file(info);
+        }
+    }
+}
+
+// The trash spec wants the path URL-escaped like the path of a file:// URL.
+fn escape_trash_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Copies into a temporary file beside `target`, syncs it, carries over timestamps and
+/// extended attributes, renames it into place and only then removes `source`.
+fn copy_across_devices(source: &Path, target: &Target, metadata: &fs::Metadata) -> io::Result<()> {
+    copy_to(source, target, metadata)?;
+    fs::remove_file(source)
+}
+
+/// Copies `source` to `target` through a temporary file beside it, removing the
+/// temporary file again if anything fails.
+fn copy_to(source: &Path, target: &Target, metadata: &fs::Metadata) -> io::Result<()> {
+    let tmp_path = tmp_path_for(&target.path)?;
+    let Err(e) = copy_into_place(source, &tmp_path, target, metadata) else {
+        return Ok(());
+    };
+    let removed = fs::remove_file(&tmp_path);
+    if e.kind() == io::ErrorKind::AlreadyExists {
+        // Callers retry on `AlreadyExists`; a leftover temporary file that cannot be
+        // cleared up would fail every retry.
+        if let Err(removed) = removed {
+            if removed.kind() != io::ErrorKind::NotFound {
+                return Err(removed);
+            }
+        }
+    }
+    Err(e)
+}
+
+fn tmp_path_for(target: &Path) -> io::Result<PathBuf> {
+    let file_name = target.file_name().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Invalid target",
+    ))?;
+    Ok(target.with_file_name(format!(".{}.nimbus-tmp", file_name.to_string_lossy())))
+}
+
+fn copy_into_place(
+    source: &Path,
+    tmp_path: &Path,
+    target: &Target,
+    metadata: &fs::Metadata,
+) -> io::Result<()> {
+    let mut reader = File::open(source)?;
+    let mut writer = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp_path)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.set_permissions(metadata.permissions())?;
+    writer.sync_all()?;
+    copy_xattrs(source, tmp_path);
+    filetime::set_file_times(
+        tmp_path,
+        FileTime::from_last_access_time(metadata),
+        FileTime::from_last_modification_time(metadata),
+    )?;
+    if target.replace {
+        fs::rename(tmp_path, &target.path)?;
+    } else {
+        rename_no_replace(tmp_path, &target.path)?;
+    }
+    sync_parent(&target.path);
+    Ok(())
+}
+
+/// Renames `from` to `to`, failing with `AlreadyExists` rather than replacing a file at
+/// `to`. The file is linked under its new name and then unlinked from the old one.
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => {
+            if let Err(e) = fs::remove_file(from) {
+                let _ = fs::remove_file(to);
+                return Err(e);
+            }
+            Ok(())
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::AlreadyExists
+                    | io::ErrorKind::CrossesDevices
+                    | io::ErrorKind::NotFound
+            ) =>
+        {
+            Err(e)
+        }
+        // Filesystems without hard links, e.g. FAT: claim the name first, then rename over
+        // the empty file that holds it.
+        Err(_) => {
+            OpenOptions::new().write(true).create_new(true).open(to)?;
+            fs::rename(from, to).inspect_err(|_| {
+                let _ = fs::remove_file(to);
+            })
+        }
+    }
+}
+
+// Best effort: some filesystems do not support extended attributes at all.
+fn copy_xattrs(source: &Path, target: &Path) {
+    let names = match xattr::list(source) {
+        Ok(names) => names,
+        Err(e) => {
+            log::debug!("Could not list xattrs of {}: {}", source.display(), e);
+            return;
+        }
+    };
+    for name in names {
+        match xattr::get(source, &name) {
+            Ok(Some(value)) => {
+                if let Err(e) = xattr::set(target, &name, &value) {
+                    log::debug!("Could not copy xattr {:?}: {}", name, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => log::debug!("Could not read xattr {:?}: {}", name, e),
+        }
+    }
+}
+
+// Makes the rename itself durable. Not every platform allows syncing a directory.
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+/// The first of `name (1).ext`, `name (2).ext`, ... that does not exist yet.
+fn free_path(target: &Path) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| target.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .expect("ran out of suffixes")
+}
+
+fn is_same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    Ok(fs::canonicalize(a)? == fs::canonicalize(b)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn write(path: &Path, contents: &str, mtime: i64) {
+        fs::write(path, contents).unwrap();
+        filetime::set_file_mtime(path, FileTime::from_unix_time(mtime, 0)).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    /// `download.pdf` holding "new" and an existing `filed/notes.pdf` holding "old".
+    fn collision(name: &str, new_mtime: i64, old_mtime: i64) -> (PathBuf, PathBuf, PathBuf) {
+        let dir = temp_dir(&format!("executor-{}", name));
+        let source = dir.join("download.pdf");
+        let target = dir.join("filed").join("notes.pdf");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        write(&source, "new", new_mtime);
+        write(&target, "old", old_mtime);
+        (dir, source, target)
+    }
+
+    #[test]
+    fn suffix_keeps_both_files() {
+        let (dir, source, target) = collision("suffix", 2_000, 1_000);
+        let outcome = MoveExecutor::new(CollisionPolicy::Suffix)
+            .move_file(&source, &target)
+            .unwrap();
+        let renamed = dir.join("filed").join("notes (1).pdf");
+        assert_eq!(outcome, MoveOutcome::Moved(renamed.clone()));
+        assert!(!source.exists());
+        assert_eq!(read(&target), "old");
+        assert_eq!(read(&renamed), "new");
+    }
+
+    #[test]
+    fn overwrite_replaces_the_existing_file() {
+        let (_dir, source, target) = collision("overwrite", 1_000, 2_000);
+        let outcome = MoveExecutor::new(CollisionPolicy::Overwrite)
+            .move_file(&source, &target)
+            .unwrap();
+        assert_eq!(outcome, MoveOutcome::Moved(target.clone()));
+        assert!(!source.exists());
+        assert_eq!(read(&target), "new");
+    }
+
+    #[test]
+    fn skip_leaves_both_files() {
+        let (_dir, source, target) = collision("skip", 2_000, 1_000);
+        let outcome = MoveExecutor::new(CollisionPolicy::Skip)
+            .move_file(&source, &target)
+            .unwrap();
+        assert_eq!(outcome, MoveOutcome::Skipped(target.clone()));
+        assert_eq!(read(&source), "new");
+        assert_eq!(read(&target), "old");
+    }
+
+    #[test]
+    fn keep_newer_replaces_only_older_files() {
+        let (_dir, source, target) = collision("keep-newer-older", 1_000, 2_000);
+        let executor = MoveExecutor::new(CollisionPolicy::KeepNewer);
+        let outcome = executor.move_file(&source, &target).unwrap();
+        assert_eq!(outcome, MoveOutcome::Skipped(target.clone()));
+        assert_eq!(read(&source), "new");
+        assert_eq!(read(&target), "old");
+
+        let (_dir, source, target) = collision("keep-newer-newer", 2_000, 1_000);
+        let outcome = executor.move_file(&source, &target).unwrap();
+        assert_eq!(outcome, MoveOutcome::Moved(target.clone()));
+        assert!(!source.exists());
+        assert_eq!(read(&target), "new");
+    }
+
+    #[test]
+    fn copy_leaves_the_source_in_place() {
+        let (dir, source, target) = collision("copy", 2_000, 1_000);
+        let outcome = MoveExecutor::new(CollisionPolicy::Suffix)
+            .copy_file(&source, &target)
+            .unwrap();
+        let copy = dir.join("filed").join("notes (1).pdf");
+        assert_eq!(outcome, MoveOutcome::Moved(copy.clone()));
+        assert_eq!(read(&source), "new");
+        assert_eq!(read(&target), "old");
+        assert_eq!(read(&copy), "new");
+    }
+
+    #[test]
+    fn missing_directories_are_created() {
+        let dir = temp_dir("executor-create-dirs");
+        let source = dir.join("download.pdf");
+        let target = dir.join("CS246").join("Lectures").join("L01.pdf");
+        write(&source, "new", 1_000);
+        let outcome = MoveExecutor::new(CollisionPolicy::Suffix)
+            .move_file(&source, &target)
+            .unwrap();
+        assert_eq!(outcome, MoveOutcome::Moved(target.clone()));
+        assert_eq!(read(&target), "new");
+    }
+
+    #[test]
+    fn free_path_picks_the_first_unused_suffix() {
+        let dir = temp_dir("executor-free-path");
+        let target = dir.join("notes.pdf");
+        assert_eq!(free_path(&target), dir.join("notes (1).pdf"));
+        fs::write(dir.join("notes (1).pdf"), "").unwrap();
+        fs::write(dir.join("notes (2).pdf"), "").unwrap();
+        assert_eq!(free_path(&target), dir.join("notes (3).pdf"));
+        assert_eq!(free_path(&dir.join("README")), dir.join("README (1)"));
+    }
+
+    #[test]
+    fn rename_no_replace_refuses_an_existing_file() {
+        let (_dir, source, target) = collision("no-replace", 2_000, 1_000);
+        let err = rename_no_replace(&source, &target).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read(&source), "new");
+        assert_eq!(read(&target), "old");
+    }
+
+    #[test]
+    fn copy_across_devices_keeps_contents_and_times() {
+        let dir = temp_dir("executor-exdev");
+        let source = dir.join("download.pdf");
+        let target = dir.join("notes.pdf");
+        write(&source, "new", 1_000);
+        let metadata = fs::metadata(&source).unwrap();
+        let placed = Target {
+            path: target.clone(),
+            replace: false,
+        };
+        copy_across_devices(&source, &placed, &metadata).unwrap();
+        assert!(!source.exists());
+        assert_eq!(read(&target), "new");
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&target).unwrap());
+        assert_eq!(mtime.unix_seconds(), 1_000);
+        assert!(!tmp_path_for(&target).unwrap().exists());
+    }
+
+    #[test]
+    fn copy_across_devices_does_not_replace_a_file_that_appeared() {
+        let (_dir, source, target) = collision("exdev-appeared", 2_000, 1_000);
+        let metadata = fs::metadata(&source).unwrap();
+        let placed = Target {
+            path: target.clone(),
+            replace: false,
+        };
+        let err = copy_across_devices(&source, &placed, &metadata).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read(&source), "new");
+        assert_eq!(read(&target), "old");
+        assert!(!tmp_path_for(&target).unwrap().exists());
+    }
+}
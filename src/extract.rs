@@ -0,0 +1,217 @@
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub type ExtractResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Limits on how much of a file is read for classification.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExtractionConfig {
+    pub enabled: bool,
+    /// Files larger than this are not opened at all.
+    pub max_file_bytes: u64,
+    /// How many PDF pages or PPTX slides to read from the start of the document.
+    pub max_pages: usize,
+    /// Extracted text is cut off after this many characters.
+    pub max_chars: usize,
+}
+
+impl Default for ExtractionConfig {
+    fn default() -> Self {
+        ExtractionConfig {
+            enabled: true,
+            max_file_bytes: 25 * 1024 * 1024,
+            max_pages: 2,
+            max_chars: 4000,
+        }
+    }
+}
+
+/// Extracts the leading text of a PDF, DOCX, PPTX, plain text or Markdown file.
+/// Returns `Ok(None)` for other formats and for files over the size limit.
+pub fn extract_text(path: &Path, config: &ExtractionConfig) -> ExtractResult<Option<String>> {
+    if !config.enabled || std::fs::metadata(path)?.len() > config.max_file_bytes {
+        return Ok(None);
+    }
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let text = match extension.as_str() {
+        "pdf" => extract_pdf(path, config)?,
+        "docx" => extract_docx(path)?,
+        "pptx" => extract_pptx(path, config)?,
+        "txt" | "md" | "markdown" => extract_plain(path, config)?,
+        _ => return Ok(None),
+    };
+    let text = truncate_chars(normalize_whitespace(&text), config.max_chars);
+    Ok(Some(text).filter(|text| !text.is_empty()))
+}
+
+fn extract_pdf(path: &Path, config: &ExtractionConfig) -> ExtractResult<String> {
+    let document = lopdf::Document::load(path)?;
+    let pages: Vec<u32> = document
+        .get_pages()
+        .into_keys()
+        .take(config.max_pages)
+        .collect();
+    Ok(document.extract_text(&pages)?)
+}
+
+fn extract_docx(path: &Path) -> ExtractResult<String> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let xml = read_zip_entry(&mut archive, "word/document.xml")?;
+    xml_text(&xml, b"w:t", b"w:p")
+}
+
+fn extract_pptx(path: &Path, config: &ExtractionConfig) -> ExtractResult<String> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut slides: Vec<(u32, String)> = archive
+        .file_names()
+        .filter_map(|name| {
+            let number = name
+                .strip_prefix("ppt/slides/slide")?
+                .strip_suffix(".xml")?
+                .parse()
+                .ok()?;
+            Some((number, name.to_string()))
+        })
+        .collect();
+    slides.sort();
+    let mut text = String::new();
+    for (_, name) in slides.into_iter().take(config.max_pages) {
+        let xml = read_zip_entry(&mut archive, &name)?;
+        text.push_str(&xml_text(&xml, b"a:t", b"a:p")?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn extract_plain(path: &Path, config: &ExtractionConfig) -> ExtractResult<String> {
+    // A UTF-8 character is at most four bytes, so this is enough for max_chars.
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take((config.max_chars * 4) as u64)
+        .read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> ExtractResult<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut xml = String::new();
+    entry.read_to_string(&mut xml)?;
+    Ok(xml)
+}
+
+/// Concatenates the text inside `text_tag` elements, breaking lines at `paragraph_tag`.
+fn xml_text(xml: &str, text_tag: &[u8], paragraph_tag: &[u8]) -> ExtractResult<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == text_tag => in_text = true,
+            Event::End(e) if e.name().as_ref() == text_tag => in_text = false,
+            Event::End(e) if e.name().as_ref() == paragraph_tag => text.push('\n'),
+            Event::Text(e) if in_text => text.push_str(&e.unescape()?),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(text)
+}
+
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate_chars(text: String, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => text[..index].to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::io::Write;
+
+    fn limited(max_chars: usize) -> ExtractionConfig {
+        ExtractionConfig {
+            max_chars,
+            ..ExtractionConfig::default()
+        }
+    }
+
+    #[test]
+    fn truncates_text_at_the_character_limit() {
+        let dir = temp_dir("extract-truncate");
+        let cases = [
+            ("notes.txt", "CS246 lecture notes", 5, Some("CS246")),
+            (
+                "notes.md",
+                "# CS246\n\nLecture   notes",
+                13,
+                Some("# CS246 Lectu"),
+            ),
+            ("notes.markdown", "short", 100, Some("short")),
+            ("notes.TXT", "short", 100, Some("short")),
+            // Characters, not bytes: each of these is two or more bytes long.
+            ("notes.txt", "été à Montréal", 6, Some("été à ")),
+            ("notes.txt", "微积分笔记", 3, Some("微积分")),
+            ("notes.txt", "  \n\t ", 100, None),
+            ("notes.csv", "course,grade", 100, None),
+        ];
+        for (name, contents, max_chars, expected) in cases {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            let text = extract_text(&path, &limited(max_chars)).unwrap();
+            assert_eq!(text.as_deref(), expected, "{}", contents);
+        }
+    }
+
+    #[test]
+    fn reads_nothing_when_disabled_or_too_large() {
+        let dir = temp_dir("extract-limits");
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "CS246 lecture notes").unwrap();
+        let disabled = ExtractionConfig {
+            enabled: false,
+            ..ExtractionConfig::default()
+        };
+        let small = ExtractionConfig {
+            max_file_bytes: 10,
+            ..ExtractionConfig::default()
+        };
+        assert_eq!(extract_text(&path, &disabled).unwrap(), None);
+        assert_eq!(extract_text(&path, &small).unwrap(), None);
+        assert!(extract_text(&dir.join("missing.txt"), &limited(100)).is_err());
+    }
+
+    #[test]
+    fn reads_docx_paragraphs() {
+        let dir = temp_dir("extract-docx");
+        let path = dir.join("A1.docx");
+        let mut archive = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        archive
+            .start_file("word/document.xml", zip::write::FileOptions::default())
+            .unwrap();
+        archive
+            .write_all(
+                br#"<w:document><w:body><w:p><w:r><w:t>CS246 </w:t></w:r><w:r><w:t>Assignment 1</w:t></w:r></w:p><w:p><w:r><w:t>Due &amp; marked</w:t></w:r></w:p></w:body></w:document>"#,
+            )
+            .unwrap();
+        archive.finish().unwrap();
+
+        assert_eq!(
+            extract_text(&path, &limited(100)).unwrap().as_deref(),
+            Some("CS246 Assignment 1 Due & marked")
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use crate::executor::{forget_trash_info, CollisionPolicy, MoveExecutor, MoveOutcome};
+use crate::setup::data_dir;
+use crate::store::{Record, SnapshotLog};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Move,
+    Copy,
+}
+
+/// Whether an operation was applied from review or by an auto-apply policy.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AppliedBy {
+    #[default]
+    Review,
+    Auto,
+}
+
+/// A file operation nimbus applied, with enough detail to reverse it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    /// The suggestion this operation came from, if any.
+    pub queue_id: Option<u64>,
+    pub operation: Operation,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// BLAKE3 hash of the file's contents when it was moved.
+    pub hash: String,
+    pub applied_at: DateTime<Local>,
+    #[serde(default)]
+    pub applied_by: AppliedBy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undone_at: Option<DateTime<Local>>,
+}
+
+impl Record for JournalEntry {
+    fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// The undo journal: every applied operation, oldest first.
+pub struct Journal {
+    log: SnapshotLog<JournalEntry>,
+}
+
+impl Journal {
+    pub fn open(dir: &Path) -> io::Result<Journal> {
+        Ok(Journal {
+            log: SnapshotLog::open(dir, "journal")?,
+        })
+    }
+
+    pub fn open_default() -> io::Result<Journal> {
+        Journal::open(&data_dir()?)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.log.flush()
+    }
+
+    /// Records a move that has already happened. `destination` is the file's new path.
+    pub fn record_move(
+        &self,
+        queue_id: Option<u64>,
+        source: &Path,
+        destination: &Path,
+        applied_by: AppliedBy,
+    ) -> io::Result<JournalEntry> {
+        self.record(Operation::Move, queue_id, source, destination, applied_by)
+    }
+
+    /// Records a copy that has already happened. `destination` is the new copy's path.
+    pub fn record_copy(
+        &self,
+        queue_id: Option<u64>,
+        source: &Path,
+        destination: &Path,
+        applied_by: AppliedBy,
+    ) -> io::Result<JournalEntry> {
+        self.record(Operation::Copy, queue_id, source, destination, applied_by)
+    }
+
+    fn record(
+        &self,
+        operation: Operation,
+        queue_id: Option<u64>,
+        source: &Path,
+        destination: &Path,
+        applied_by: AppliedBy,
+    ) -> io::Result<JournalEntry> {
+        let hash = hash_file(destination)?;
+        self.log.insert(|id| JournalEntry {
+            id,
+            queue_id,
+            operation,
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            hash,
+            applied_at: Local::now(),
+            applied_by,
+            undone_at: None,
+        })
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<JournalEntry>> {
+        self.log.all()
+    }
+
+    /// Whether a file with these contents was moved away from `source` and later moved
+    /// back by an undo. A different download that reuses the name does not count.
+    pub fn was_undone(&self, source: &Path, hash: &str) -> io::Result<bool> {
+        Ok(self
+            .entries()?
+            .iter()
+            .any(|entry| entry.source == source && entry.hash == hash && entry.undone_at.is_some()))
+    }
+
+    /// The `last` most recent operations that have not been undone, newest first.
+    pub fn last_applied(&self, last: usize) -> io::Result<Vec<JournalEntry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .rev()
+            .filter(|entry| entry.undone_at.is_none())
+            .take(last)
+            .collect())
+    }
+
+    pub fn get(&self, id: u64) -> io::Result<Option<JournalEntry>> {
+        self.log.get(id)
+    }
+
+    /// Moves a file back to where it came from, or deletes the copy that was made,
+    /// refusing if it has changed or moved since.
+    pub fn undo(&self, entry: &JournalEntry) -> Result<JournalEntry, Box<dyn std::error::Error>> {
+        if entry.undone_at.is_some() {
+            return Err(format!("#{} has already been undone", entry.id).into());
+        }
+        if !entry.destination.exists() {
+            return Err(format!(
+                "#{}: {} has been moved or deleted since",
+                entry.id,
+                entry.destination.display()
+            )
+            .into());
+        }
+        if hash_file(&entry.destination)? != entry.hash {
+            return Err(format!(
+                "#{}: {} has changed since it was moved",
+                entry.id,
+                entry.destination.display()
+            )
+            .into());
+        }
+        if entry.operation == Operation::Copy {
+            std::fs::remove_file(&entry.destination)?;
+            return Ok(self
+                .log
+                .modify(entry.id, |e| e.undone_at = Some(Local::now()))?);
+        }
+        if entry.source.exists() {
+            return Err(format!(
+                "#{}: something already exists at {}",
+                entry.id,
+                entry.source.display()
+            )
+            .into());
+        }
+        let executor = MoveExecutor::new(CollisionPolicy::Skip);
+        if let MoveOutcome::Skipped(existing) =
+            executor.move_file(&entry.destination, &entry.source)?
+        {
+            return Err(format!(
+                "#{}: something already exists at {}",
+                entry.id,
+                existing.display()
+            )
+            .into());
+        }
+        forget_trash_info(&entry.destination);
+        Ok(self
+            .log
+            .modify(entry.id, |e| e.undone_at = Some(Local::now()))?)
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Undoes `id`, or the most recent `last` operations that have not been undone yet.
+pub fn undo_operations(id: Option<u64>, last: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let journal = Journal::open_default()?;
+    let targets = match id {
+        Some(id) => vec![journal
+            .get(id)?
+            .ok_or(format!("No operation with id {}", id))?],
+        None => journal.last_applied(last)?,
+    };
+    if targets.is_empty() {
+        println!("Nothing to undo");
+        return Ok(());
+    }
+    let mut failures = 0;
+    for entry in targets {
+        match journal.undo(&entry) {
+            Ok(entry) if entry.operation == Operation::Copy => println!(
+                "Undid #{}: removed copy {}",
+                entry.id,
+                entry.destination.display()
+            ),
+            Ok(entry) => println!(
+                "Undid #{}: {} -> {}",
+                entry.id,
+                entry.destination.display(),
+                entry.source.display()
+            ),
+            Err(e) => {
+                failures += 1;
+                eprintln!("{}", e);
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} operation(s) could not be undone", failures).into());
+    }
+    Ok(())
+}
+
+pub fn show_history(limit: usize, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let journal = Journal::open_default()?;
+    let entries: Vec<JournalEntry> = journal.entries()?.into_iter().rev().take(limit).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    println!(
+        "{:>5}  {:<4}  {:<16}  {:<6}  {:<6}  {:<40}  DESTINATION",
+        "ID", "OP", "APPLIED", "BY", "UNDONE", "SOURCE"
+    );
+    for entry in entries {
+        println!(
+            "{:>5}  {:<4}  {:<16}  {:<6}  {:<6}  {:<40}  {}",
+            entry.id,
+            format!("{:?}", entry.operation).to_lowercase(),
+            entry.applied_at.format("%Y-%m-%d %H:%M"),
+            format!("{:?}", entry.applied_by).to_lowercase(),
+            if entry.undone_at.is_some() {
+                "yes"
+            } else {
+                "no"
+            },
+            entry.source.display(),
+            entry.destination.display(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::fs;
+
+    /// A journal in its own directory, with `Downloads/L01.pdf` holding "slides".
+    fn setup(name: &str) -> (PathBuf, Journal, PathBuf) {
+        let dir = temp_dir(&format!("journal-{}", name));
+        let journal = Journal::open(&dir.join("data")).unwrap();
+        let source = dir.join("Downloads").join("L01.pdf");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, "slides").unwrap();
+        (dir, journal, source)
+    }
+
+    fn moved(source: &Path, destination: &Path) {
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        fs::rename(source, destination).unwrap();
+    }
+
+    #[test]
+    fn undo_moves_the_file_back() {
+        let (dir, journal, source) = setup("move");
+        let destination = dir.join("CS246").join("L01.pdf");
+        moved(&source, &destination);
+        let entry = journal
+            .record_move(Some(1), &source, &destination, AppliedBy::Review)
+            .unwrap();
+
+        let undone = journal.undo(&entry).unwrap();
+        assert!(undone.undone_at.is_some());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "slides");
+        assert!(!destination.exists());
+        let err = journal.undo(&undone).unwrap_err();
+        assert!(err.to_string().contains("already been undone"), "{}", err);
+    }
+
+    #[test]
+    fn undo_removes_a_copy() {
+        let (dir, journal, source) = setup("copy");
+        let destination = dir.join("CS246").join("L01.pdf");
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        fs::copy(&source, &destination).unwrap();
+        let entry = journal
+            .record_copy(None, &source, &destination, AppliedBy::Auto)
+            .unwrap();
+
+        journal.undo(&entry).unwrap();
+        assert!(!destination.exists());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "slides");
+    }
+
+    #[test]
+    fn undo_takes_a_file_out_of_the_trash() {
+        let (dir, journal, source) = setup("trash");
+        let trashed = dir.join("Trash").join("files").join("L01.pdf");
+        let info = dir.join("Trash").join("info").join("L01.pdf.trashinfo");
+        moved(&source, &trashed);
+        fs::create_dir_all(info.parent().unwrap()).unwrap();
+        fs::write(&info, "[Trash Info]\n").unwrap();
+        let entry = journal
+            .record_move(Some(1), &source, &trashed, AppliedBy::Review)
+            .unwrap();
+
+        journal.undo(&entry).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "slides");
+        assert!(!trashed.exists());
+        assert!(!info.exists());
+    }
+
+    #[test]
+    fn undo_refuses_a_file_that_changed_since() {
+        let (dir, journal, source) = setup("changed");
+        let destination = dir.join("CS246").join("L01.pdf");
+        moved(&source, &destination);
+        let entry = journal
+            .record_move(Some(1), &source, &destination, AppliedBy::Review)
+            .unwrap();
+        fs::write(&destination, "annotated slides").unwrap();
+
+        let err = journal.undo(&entry).unwrap_err();
+        assert!(err.to_string().contains("has changed"), "{}", err);
+        assert!(!source.exists());
+        assert_eq!(
+            fs::read_to_string(&destination).unwrap(),
+            "annotated slides"
+        );
+        assert!(journal.get(entry.id).unwrap().unwrap().undone_at.is_none());
+    }
+
+    #[test]
+    fn undo_refuses_to_replace_a_new_file_at_the_source() {
+        let (dir, journal, source) = setup("source-taken");
+        let destination = dir.join("CS246").join("L01.pdf");
+        moved(&source, &destination);
+        let entry = journal
+            .record_move(Some(1), &source, &destination, AppliedBy::Review)
+            .unwrap();
+        fs::write(&source, "a new download").unwrap();
+
+        let err = journal.undo(&entry).unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+        assert_eq!(fs::read_to_string(&source).unwrap(), "a new download");
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "slides");
+    }
+
+    #[test]
+    fn last_applied_skips_undone_operations() {
+        let (dir, journal, _) = setup("last");
+        let mut ids = Vec::new();
+        for n in 0..3 {
+            let source = dir.join("Downloads").join(format!("L0{}.pdf", n));
+            let destination = dir.join("CS246").join(format!("L0{}.pdf", n));
+            fs::create_dir_all(destination.parent().unwrap()).unwrap();
+            fs::write(&destination, format!("slides {}", n)).unwrap();
+            let entry = journal
+                .record_move(None, &source, &destination, AppliedBy::Review)
+                .unwrap();
+            ids.push(entry.id);
+        }
+        let newest = journal.get(ids[2]).unwrap().unwrap();
+        journal.undo(&newest).unwrap();
+
+        let last: Vec<u64> = journal
+            .last_applied(5)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(last, vec![ids[1], ids[0]]);
+        assert_eq!(journal.last_applied(1).unwrap()[0].id, ids[1]);
+    }
+
+    #[test]
+    fn was_undone_matches_contents_not_just_the_name() {
+        let (dir, journal, source) = setup("was-undone");
+        let destination = dir.join("CS246").join("L01.pdf");
+        moved(&source, &destination);
+        let entry = journal
+            .record_move(Some(1), &source, &destination, AppliedBy::Auto)
+            .unwrap();
+        assert!(!journal.was_undone(&source, &entry.hash).unwrap());
+        journal.undo(&entry).unwrap();
+
+        assert!(journal.was_undone(&source, &entry.hash).unwrap());
+        fs::write(&source, "next week's slides").unwrap();
+        let other = hash_file(&source).unwrap();
+        assert!(!journal.was_undone(&source, &other).unwrap());
+    }
+}
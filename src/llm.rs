@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Where to send chat-completion requests. Anything that speaks the OpenAI
+/// `/chat/completions` API works, including a local llama.cpp or Ollama server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LlmConfig {
+    pub enabled: bool,
+    /// e.g. `https://api.openai.com/v1` or `http://localhost:11434/v1`.
+    pub base_url: String,
+    pub model: String,
+    /// Key sent as a bearer token. Local servers usually need none.
+    pub api_key: Option<String>,
+    /// Environment variable to read the key from when `api_key` is not set.
+    pub api_key_env: Option<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            enabled: true,
+            base_url: OPENAI_BASE_URL.to_string(),
+            model: "gpt-3.5-turbo".to_string(),
+            api_key: None,
+            api_key_env: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// The variable nimbus has always read the OpenAI key from.
+pub const LEGACY_API_KEY_ENV: &str = "GPT_API_KEY";
+
+impl LlmConfig {
+    /// `api_key`, then the variable `api_key_env` names. Without either, OpenAI still
+    /// gets the key from `GPT_API_KEY`; other servers get no key.
+    pub fn resolve_api_key(&self) -> Option<String> {
+        self.resolve_api_key_from(|name| std::env::var(name).ok())
+    }
+
+    /// `resolve_api_key`, reading environment variables through `var`.
+    fn resolve_api_key_from(&self, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let api_key_env = match &self.api_key_env {
+            Some(name) => Some(name.as_str()),
+            None if self.base_url.trim_end_matches('/') == OPENAI_BASE_URL => {
+                Some(LEGACY_API_KEY_ENV)
+            }
+            None => None,
+        };
+        self.api_key
+            .clone()
+            .or_else(|| api_key_env.and_then(var))
+            .filter(|key| !key.trim().is_empty())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    temperature: f32,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChatMessage,
+}
+
+/// A minimal client for OpenAI-compatible chat-completion endpoints.
+pub struct ChatClient {
+    http: reqwest::Client,
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl ChatClient {
+    pub fn new(config: &LlmConfig) -> Result<ChatClient, reqwest::Error> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        Ok(ChatClient {
+            http,
+            endpoint: format!("{}/chat/completions", config.base_url.trim_end_matches('/')),
+            model: config.model.clone(),
+            api_key: config.resolve_api_key(),
+        })
+    }
+
+    /// Sends the conversation and returns the content of the first choice.
+    pub async fn complete(
+        &self,
+        messages: &[ChatMessage],
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut request = self.http.post(&self.endpoint).json(&CompletionRequest {
+            model: &self.model,
+            messages,
+            temperature: 0.0,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{} returned {}: {}", self.endpoint, status, body).into());
+        }
+        let completion: CompletionResponse = response.json().await?;
+        let choice = completion
+            .choices
+            .into_iter()
+            .next()
+            .ok_or("Completion had no choices")?;
+        Ok(choice.message.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(base_url: &str, api_key: Option<&str>, api_key_env: Option<&str>) -> LlmConfig {
+        LlmConfig {
+            base_url: base_url.to_string(),
+            api_key: api_key.map(str::to_string),
+            api_key_env: api_key_env.map(str::to_string),
+            ..LlmConfig::default()
+        }
+    }
+
+    #[test]
+    fn resolves_the_api_key() {
+        let env = |name: &str| match name {
+            LEGACY_API_KEY_ENV => Some("sk-legacy".to_string()),
+            "OLLAMA_KEY" => Some("ollama".to_string()),
+            "BLANK_KEY" => Some(" ".to_string()),
+            _ => None,
+        };
+        let local = "http://localhost:11434/v1";
+        let cases = [
+            (config(OPENAI_BASE_URL, None, None), Some("sk-legacy")),
+            (
+                config("https://api.openai.com/v1/", None, None),
+                Some("sk-legacy"),
+            ),
+            (
+                config(OPENAI_BASE_URL, Some("sk-config"), None),
+                Some("sk-config"),
+            ),
+            (
+                config(OPENAI_BASE_URL, None, Some("OLLAMA_KEY")),
+                Some("ollama"),
+            ),
+            // The OpenAI key is never sent to another host unless asked for.
+            (config(local, None, None), None),
+            (
+                config("https://api.openai.com.evil.test/v1", None, None),
+                None,
+            ),
+            (
+                config(local, None, Some(LEGACY_API_KEY_ENV)),
+                Some("sk-legacy"),
+            ),
+            (config(local, None, Some("OLLAMA_KEY")), Some("ollama")),
+            (
+                config(local, Some("local"), Some("OLLAMA_KEY")),
+                Some("local"),
+            ),
+            (config(local, None, Some("MISSING_KEY")), None),
+            (config(local, None, Some("BLANK_KEY")), None),
+            (config(OPENAI_BASE_URL, Some(""), None), None),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(
+                config.resolve_api_key_from(env).as_deref(),
+                expected,
+                "{:?}",
+                config
+            );
+        }
+    }
+}
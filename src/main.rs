@@ -1,31 +1,133 @@
 use clap::Parser;
-use cli::Commands;
+use cli::{Commands, ModelCommands, ReviewCommands};
 use monitor::start_monitor;
+pub mod auto_apply;
+pub mod classifier;
 pub mod cli;
+pub mod control;
+pub mod decisions;
+pub mod duplicates;
+pub mod executor;
+pub mod extract;
+pub mod journal;
+pub mod llm;
+pub mod matcher;
+pub mod model;
 pub mod monitor;
+pub mod naming;
+pub mod origin;
+pub mod queue;
+pub mod readiness;
 pub mod review;
+pub mod roots;
+pub mod rules;
 pub mod setup;
+pub mod store;
+pub mod subfolders;
+pub mod tags;
+#[cfg(test)]
+mod testing;
 use dotenv::dotenv;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let logger = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info,lopdf=warn"),
+    )
+    .build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(control::ErrorRecorder::new(logger)))
+        .expect("logger already set");
+    log::set_max_level(max_level);
     let nimbus = cli::Nimbus::parse();
+    let config_path = match setup::config_path(nimbus.config) {
+        Ok(path) => path,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     match nimbus.command {
         Commands::Config => {
             // Handle 'nimbus config' here
-            setup::setup_nimbus().await.unwrap();
+            setup::setup_nimbus(&config_path).await.unwrap();
         }
-        Commands::Review => {
+        Commands::Review { action } => {
             // Handle 'nimbus review' here
-            log::info!("Reviewing...");
-            review::read_commands().unwrap();
+            let result = match action {
+                None => {
+                    log::info!("Reviewing...");
+                    review::read_commands(&config_path)
+                }
+                Some(ReviewCommands::List { json, all }) => review::list_commands(json, all),
+                Some(ReviewCommands::Accept(selection)) => {
+                    review::accept_commands(&config_path, &selection)
+                }
+                Some(ReviewCommands::Reject(selection)) => review::reject_commands(&selection),
+            };
+            if let Err(e) = result {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
         }
-        Commands::Start => match start_monitor() {
-            Ok(_) => log::info!("monitor started"),
-            Err(e) => log::error!("Failed to start monitor: {}", e),
+        Commands::Start => match start_monitor(&config_path) {
+            Ok(_) => log::info!("monitor stopped"),
+            Err(e) => {
+                log::error!("Monitor failed: {}", e);
+                std::process::exit(1);
+            }
         },
+        Commands::Status { json } => {
+            if let Err(e) = control::show_status(json) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Pause | Commands::Resume | Commands::Reload | Commands::Stop => {
+            let request = match nimbus.command {
+                Commands::Pause => control::Request::Pause,
+                Commands::Resume => control::Request::Resume,
+                Commands::Reload => control::Request::Reload,
+                _ => control::Request::Stop,
+            };
+            if let Err(e) = control::control(request) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Undo { id, last } => {
+            if let Err(e) = journal::undo_operations(id, last) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Scan { since } => {
+            if let Err(e) = monitor::scan_downloads(&config_path, since).await {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Train => {
+            if let Err(e) = model::train_model() {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Model {
+            action: ModelCommands::Stats { json },
+        } => {
+            if let Err(e) = model::show_stats(json) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::History { limit, json } => {
+            if let Err(e) = journal::show_history(limit, json) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -0,0 +1,588 @@
+use crate::setup::Course;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// A course code split into its subject and catalog number, e.g. `CS` + `246` + `E`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CourseCode {
+    pub subject: String,
+    pub number: String,
+    /// Letters glued to the catalog number, like the `E` in `CS246E`.
+    pub suffix: String,
+}
+
+/// What tied a file to a course, from weakest to most specific evidence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchRule {
+    Keyword,
+    Alias,
+    Code,
+    Pattern,
+    OrgUnit,
+}
+
+/// A configured course that a piece of text mentions, and how confidently.
+#[derive(Debug, Clone)]
+pub struct CourseMatch {
+    pub course: Course,
+    pub score: f32,
+    pub rule: MatchRule,
+}
+
+// Separators allowed between subject and number: `CS 246`, `CS-246`, `CS_246`, `CS.246`.
+const MAX_SEPARATORS: usize = 3;
+const MIN_SUBJECT_LEN: usize = 2;
+const MAX_SUBJECT_LEN: usize = 8;
+const MIN_NUMBER_LEN: usize = 2;
+const MAX_NUMBER_LEN: usize = 4;
+// A mention with trailing letters the course does not have, like `cs246a1` for CS246.
+const LOOSE_SUFFIX_SCORE: f32 = 0.9;
+const ALIAS_SCORE: f32 = 0.9;
+const KEYWORD_SCORE: f32 = 0.6;
+// A course code in the body is good evidence, but slides often cite other courses.
+const TEXT_MATCH_WEIGHT: f32 = 0.8;
+
+/// Parses a configured course name such as `CS246`, `cs 246` or `CS246E`.
+pub fn parse_course_code(name: &str) -> Option<CourseCode> {
+    let mentions = find_mentions(name);
+    match mentions.as_slice() {
+        [code] => Some(code.clone()),
+        _ => None,
+    }
+}
+
+/// Finds every course-code-shaped token in `text`.
+///
+/// The subject must be a whole run of letters and the number a whole run of digits, so
+/// `CS24` never matches `CS246` and `ABCS246` never matches `CS246`.
+pub fn find_mentions(text: &str) -> Vec<CourseCode> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut mentions = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_alphabetic() || (i > 0 && chars[i - 1].is_ascii_alphabetic()) {
+            i += 1;
+            continue;
+        }
+        let subject_end = run_end(&chars, i, |c| c.is_ascii_alphabetic());
+        let mut number_start = subject_end;
+        while number_start < chars.len()
+            && number_start - subject_end < MAX_SEPARATORS
+            && is_separator(chars[number_start])
+        {
+            number_start += 1;
+        }
+        let number_end = run_end(&chars, number_start, |c| c.is_ascii_digit());
+        let suffix_end = run_end(&chars, number_end, |c| c.is_ascii_alphabetic());
+
+        let subject_len = subject_end - i;
+        let number_len = number_end - number_start;
+        if (MIN_SUBJECT_LEN..=MAX_SUBJECT_LEN).contains(&subject_len)
+            && (MIN_NUMBER_LEN..=MAX_NUMBER_LEN).contains(&number_len)
+        {
+            mentions.push(CourseCode {
+                subject: collect_upper(&chars[i..subject_end]),
+                number: collect_upper(&chars[number_start..number_end]),
+                suffix: collect_upper(&chars[number_end..suffix_end]),
+            });
+        }
+        i = subject_end;
+    }
+    mentions
+}
+
+/// Scores every course whose code or aliases `text` mentions, most specific first.
+/// Each course appears at most once.
+pub fn match_courses(courses: &[Course], text: &str) -> Vec<CourseMatch> {
+    let text = decode_spaces(text);
+    let mentions = find_mentions(&text);
+    let words = words(&text);
+    let mut matches = Vec::new();
+    for course in courses {
+        let mut best: Option<CourseMatch> = None;
+        for name in std::iter::once(&course.name).chain(&course.aliases) {
+            let (rule, score) = match parse_course_code(name) {
+                Some(code) => (
+                    MatchRule::Code,
+                    mentions
+                        .iter()
+                        .map(|mention| score_mention(&code, mention))
+                        .fold(0.0, f32::max),
+                ),
+                // Names that are not course codes fall back to a plain substring match.
+                None if name == &course.name => {
+                    let found = normalize(&text).contains(&normalize(name));
+                    (MatchRule::Code, if found { 1.0 } else { 0.0 })
+                }
+                None => {
+                    let found = contains_phrase(&words, &self::words(name));
+                    (MatchRule::Alias, if found { ALIAS_SCORE } else { 0.0 })
+                }
+            };
+            if score > 0.0 {
+                keep_best(
+                    &mut best,
+                    CourseMatch {
+                        course: course.clone(),
+                        score,
+                        rule,
+                    },
+                );
+            }
+        }
+        matches.extend(best);
+    }
+    sort_matches(&mut matches);
+    matches
+}
+
+/// LEARN (D2L) org-unit IDs in a URL, from paths like `/d2l/le/content/912345/` and
+/// `/content/enforced/912345-CS246_1239/` or an `ou=912345` query parameter.
+pub fn d2l_org_units(url: &str) -> Vec<u64> {
+    static ORG_UNIT: OnceLock<Regex> = OnceLock::new();
+    let org_unit = ORG_UNIT.get_or_init(|| {
+        Regex::new(r"/d2l/(?:le/[a-z]+|home)/(\d+)|[?&]ou=(\d+)|/content/enforced/(\d+)-")
+            .expect("valid org unit regex")
+    });
+    org_unit
+        .captures_iter(url)
+        .filter_map(|captures| {
+            captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .next()
+                .and_then(|id| id.as_str().parse().ok())
+        })
+        .collect()
+}
+
+/// Matches files against every rule configured for the courses.
+pub struct CourseMatcher {
+    courses: Vec<Course>,
+    patterns: Vec<Vec<Regex>>,
+}
+
+impl CourseMatcher {
+    /// Fails if one of the courses has an invalid regex pattern.
+    pub fn new(courses: Vec<Course>) -> Result<CourseMatcher, regex::Error> {
+        let patterns = courses
+            .iter()
+            .map(|course| course.patterns.iter().map(|p| Regex::new(p)).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(CourseMatcher { courses, patterns })
+    }
+
+    pub fn courses(&self) -> &[Course] {
+        &self.courses
+    }
+
+    /// Every course the file could belong to, most specific evidence first. The file's
+    /// text is only searched when neither its name nor its origin URLs mention a course.
+    pub fn candidates(&self, name: &str, urls: &[&str], text: Option<&str>) -> Vec<CourseMatch> {
+        let mut matches = Vec::new();
+        for source in std::iter::once(name).chain(urls.iter().copied()) {
+            matches.extend(match_courses(&self.courses, source));
+            matches.extend(self.pattern_matches(source));
+            matches.extend(self.keyword_matches(source));
+        }
+        for url in urls {
+            matches.extend(self.org_unit_matches(url));
+        }
+        if matches.is_empty() {
+            if let Some(text) = text {
+                matches.extend(match_courses(&self.courses, text));
+                matches.extend(self.keyword_matches(text));
+                for candidate in &mut matches {
+                    candidate.score *= TEXT_MATCH_WEIGHT;
+                }
+            }
+        }
+        let mut best: Vec<CourseMatch> = Vec::new();
+        for candidate in matches {
+            match best
+                .iter_mut()
+                .find(|m| m.course.name == candidate.course.name)
+            {
+                Some(existing) => {
+                    if (candidate.rule, candidate.score) > (existing.rule, existing.score) {
+                        *existing = candidate;
+                    }
+                }
+                None => best.push(candidate),
+            }
+        }
+        sort_matches(&mut best);
+        best
+    }
+
+    fn pattern_matches(&self, source: &str) -> Vec<CourseMatch> {
+        self.courses
+            .iter()
+            .zip(&self.patterns)
+            .filter(|(_, patterns)| patterns.iter().any(|p| p.is_match(source)))
+            .map(|(course, _)| CourseMatch {
+                course: course.clone(),
+                score: 1.0,
+                rule: MatchRule::Pattern,
+            })
+            .collect()
+    }
+
+    fn keyword_matches(&self, source: &str) -> Vec<CourseMatch> {
+        let words = words(&decode_spaces(source));
+        self.courses
+            .iter()
+            .filter(|course| {
+                course
+                    .keywords
+                    .iter()
+                    .any(|keyword| contains_phrase(&words, &self::words(keyword)))
+            })
+            .map(|course| CourseMatch {
+                course: course.clone(),
+                score: KEYWORD_SCORE,
+                rule: MatchRule::Keyword,
+            })
+            .collect()
+    }
+
+    fn org_unit_matches(&self, url: &str) -> Vec<CourseMatch> {
+        let ids = d2l_org_units(url);
+        self.courses
+            .iter()
+            .filter(|course| course.d2l_ids.iter().any(|id| ids.contains(id)))
+            .map(|course| CourseMatch {
+                course: course.clone(),
+                score: 1.0,
+                rule: MatchRule::OrgUnit,
+            })
+            .collect()
+    }
+}
+
+fn keep_best(best: &mut Option<CourseMatch>, candidate: CourseMatch) {
+    let better = best
+        .as_ref()
+        .is_none_or(|b| (candidate.rule, candidate.score) > (b.rule, b.score));
+    if better {
+        *best = Some(candidate);
+    }
+}
+
+fn sort_matches(matches: &mut [CourseMatch]) {
+    matches.sort_by(|a, b| {
+        b.rule
+            .cmp(&a.rule)
+            .then_with(|| b.score.total_cmp(&a.score))
+    });
+}
+
+fn score_mention(code: &CourseCode, mention: &CourseCode) -> f32 {
+    if code.subject != mention.subject || code.number != mention.number {
+        return 0.0;
+    }
+    if code.suffix == mention.suffix {
+        1.0
+    } else if mention.suffix.starts_with(&code.suffix) {
+        LOOSE_SUFFIX_SCORE
+    } else {
+        0.0
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '_' | '-' | '.')
+}
+
+fn run_end<F: Fn(char) -> bool>(chars: &[char], start: usize, in_run: F) -> usize {
+    let mut end = start;
+    while end < chars.len() && in_run(chars[end]) {
+        end += 1;
+    }
+    end
+}
+
+fn collect_upper(chars: &[char]) -> String {
+    chars.iter().map(|c| c.to_ascii_uppercase()).collect()
+}
+
+fn decode_spaces(text: &str) -> String {
+    text.replace("%20", " ")
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Whether `phrase` appears in `words` as a run of whole words.
+fn contains_phrase(words: &[String], phrase: &[String]) -> bool {
+    !phrase.is_empty() && words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course(name: &str) -> Course {
+        Course {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn best(courses: &[&str], text: &str) -> Option<(String, f32)> {
+        let courses: Vec<Course> = courses.iter().map(|name| course(name)).collect();
+        match_courses(&courses, text)
+            .into_iter()
+            .next()
+            .map(|m| (m.course.name, m.score))
+    }
+
+    #[test]
+    fn matches_course_codes_in_any_spelling() {
+        let cases = [
+            ("CS246_a1.pdf", Some("CS246")),
+            ("cs 246 notes.pdf", Some("CS246")),
+            ("cs-246.pdf", Some("CS246")),
+            ("CS_246 Midterm.pdf", Some("CS246")),
+            ("Cs246.pdf", Some("CS246")),
+            ("CS.246.slides.pdf", Some("CS246")),
+            ("Lecture 3 - MATH 239.pdf", Some("MATH239")),
+            (
+                "https://learn.uwaterloo.ca/content/CS%20246/a1.pdf",
+                Some("CS246"),
+            ),
+            ("2024CS246.pdf", Some("CS246")),
+            ("CS24.pdf", None),
+            ("CS2460.pdf", None),
+            ("ABCS246.pdf", None),
+            ("CS 2 46.pdf", None),
+            ("receipt.pdf", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            let actual = best(&["CS246", "MATH239"], text).map(|(name, _)| name);
+            assert_eq!(actual.as_deref(), expected, "text: {:?}", text);
+        }
+    }
+
+    #[test]
+    fn scores_trailing_letters() {
+        let cases = [
+            (&["CS246"][..], "CS246.pdf", Some(("CS246", 1.0))),
+            (
+                &["CS246"][..],
+                "cs246a1.pdf",
+                Some(("CS246", LOOSE_SUFFIX_SCORE)),
+            ),
+            (
+                &["CS246", "CS246E"][..],
+                "cs246e notes.pdf",
+                Some(("CS246E", 1.0)),
+            ),
+            (
+                &["CS246", "CS246E"][..],
+                "cs246 notes.pdf",
+                Some(("CS246", 1.0)),
+            ),
+            (&["CS246E"][..], "cs246 notes.pdf", None),
+        ];
+        for (courses, text, expected) in cases {
+            let actual = best(courses, text);
+            let actual = actual.as_ref().map(|(name, score)| (name.as_str(), *score));
+            assert_eq!(actual, expected, "courses: {:?}, text: {:?}", courses, text);
+        }
+    }
+
+    #[test]
+    fn returns_every_candidate() {
+        let courses: Vec<Course> = ["CS246", "MATH239", "STAT230"]
+            .iter()
+            .map(|name| course(name))
+            .collect();
+        let matches = match_courses(&courses, "CS 246 and MATH239a review.pdf");
+        let names: Vec<(&str, f32)> = matches
+            .iter()
+            .map(|m| (m.course.name.as_str(), m.score))
+            .collect();
+        assert_eq!(names, vec![("CS246", 1.0), ("MATH239", LOOSE_SUFFIX_SCORE)]);
+    }
+
+    #[test]
+    fn parses_configured_names() {
+        let cases = [
+            ("CS246", Some(("CS", "246", ""))),
+            ("cs 246e", Some(("CS", "246", "E"))),
+            ("MATH-135", Some(("MATH", "135", ""))),
+            ("Intro to CS", None),
+            ("CS246 MATH239", None),
+        ];
+        for (name, expected) in cases {
+            let actual = parse_course_code(name);
+            let actual = actual
+                .as_ref()
+                .map(|c| (c.subject.as_str(), c.number.as_str(), c.suffix.as_str()));
+            assert_eq!(actual, expected, "name: {:?}", name);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_substring_for_non_code_names() {
+        assert_eq!(
+            best(&["Co-op"], "coop offer letter.pdf").map(|(name, _)| name),
+            Some("Co-op".to_string())
+        );
+    }
+
+    fn rules_fixture() -> CourseMatcher {
+        CourseMatcher::new(vec![
+            Course {
+                name: "CO250".to_string(),
+                aliases: vec!["CS 250".to_string(), "Optimization".to_string()],
+                ..Default::default()
+            },
+            Course {
+                name: "ECE240".to_string(),
+                aliases: vec!["ECE 2xx Circuits".to_string()],
+                keywords: vec!["Kirchhoff".to_string()],
+                d2l_ids: vec![912345],
+                ..Default::default()
+            },
+            Course {
+                name: "MATH239".to_string(),
+                patterns: vec![r"(?i)^graph[-_ ]theory".to_string()],
+                keywords: vec!["Kirchhoff".to_string()],
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn applies_course_rules() {
+        let matcher = rules_fixture();
+        // File name, origin URLs, text, expected best course and rule.
+        type Case<'a> = (
+            &'a str,
+            &'a [&'a str],
+            Option<&'a str>,
+            Option<(&'a str, MatchRule)>,
+        );
+        let cases: [Case; 9] = [
+            (
+                "cs-250 notes.pdf",
+                &[],
+                None,
+                Some(("CO250", MatchRule::Code)),
+            ),
+            (
+                "optimization week 3.pdf",
+                &[],
+                None,
+                Some(("CO250", MatchRule::Alias)),
+            ),
+            (
+                "ece 2xx circuits lab.pdf",
+                &[],
+                None,
+                Some(("ECE240", MatchRule::Alias)),
+            ),
+            (
+                "graph_theory_notes.pdf",
+                &[],
+                None,
+                Some(("MATH239", MatchRule::Pattern)),
+            ),
+            ("my graph_theory.pdf", &[], None, None),
+            (
+                "L01.pdf",
+                &["https://learn.uwaterloo.ca/d2l/le/content/912345/viewContent/1/View"],
+                None,
+                Some(("ECE240", MatchRule::OrgUnit)),
+            ),
+            (
+                "CO250 slides.pdf",
+                &["https://learn.uwaterloo.ca/content/enforced/912345-ECE240_1239/a.pdf"],
+                None,
+                Some(("ECE240", MatchRule::OrgUnit)),
+            ),
+            ("kirchhoffs.pdf", &[], None, None),
+            (
+                "scan.pdf",
+                &[],
+                Some("see MATH 239 notes"),
+                Some(("MATH239", MatchRule::Code)),
+            ),
+        ];
+        for (name, urls, text, expected) in cases {
+            let best = matcher.candidates(name, urls, text).into_iter().next();
+            let actual = best.as_ref().map(|m| (m.course.name.as_str(), m.rule));
+            assert_eq!(actual, expected, "name: {:?}, urls: {:?}", name, urls);
+        }
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let matcher = rules_fixture();
+        let candidates = matcher.candidates(
+            "Kirchhoff laws - graph theory.pdf",
+            &["https://learn.uwaterloo.ca/d2l/home/912345"],
+            None,
+        );
+        let actual: Vec<(&str, MatchRule)> = candidates
+            .iter()
+            .map(|m| (m.course.name.as_str(), m.rule))
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                ("ECE240", MatchRule::OrgUnit),
+                ("MATH239", MatchRule::Keyword)
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_d2l_org_units() {
+        let cases: [(&str, &[u64]); 5] = [
+            (
+                "https://learn.uwaterloo.ca/d2l/le/content/912345/Home",
+                &[912345],
+            ),
+            ("https://learn.uwaterloo.ca/d2l/home/912345", &[912345]),
+            (
+                "https://learn.uwaterloo.ca/d2l/lms/dropbox/user/folders_list.d2l?ou=912345",
+                &[912345],
+            ),
+            (
+                "https://learn.uwaterloo.ca/content/enforced/912345-CS246_1239/L01.pdf",
+                &[912345],
+            ),
+            ("https://example.com/files/912345/L01.pdf", &[]),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(d2l_org_units(url), expected, "url: {:?}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let course = Course {
+            name: "CS246".to_string(),
+            patterns: vec!["(unclosed".to_string()],
+            ..Default::default()
+        };
+        assert!(CourseMatcher::new(vec![course]).is_err());
+    }
+}
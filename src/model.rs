@@ -0,0 +1,344 @@
+use crate::decisions::{Action, Decision, DecisionLog};
+use crate::setup::data_dir;
+use chrono::{DateTime, Local};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What the model looks at: the words in a file's name, where it was downloaded
+/// from, and its extension.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Features {
+    pub tokens: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_host: Option<String>,
+    /// Words in the URL's path.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub url_path: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+}
+
+impl Features {
+    pub fn extract(file_name: &str, url: Option<&str>) -> Features {
+        let path = Path::new(file_name);
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let url = url.and_then(|url| Url::parse(url).ok());
+        Features {
+            tokens: words(&stem),
+            url_host: url
+                .as_ref()
+                .and_then(|url| url.host_str())
+                .map(str::to_string),
+            url_path: url
+                .as_ref()
+                .map(|url| words(url.path()))
+                .unwrap_or_default(),
+            extension: path
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase()),
+        }
+    }
+
+    /// The features as prefixed terms, so a path word never collides with a name word.
+    fn terms(&self) -> Vec<String> {
+        let mut terms: Vec<String> = self.tokens.iter().map(|t| format!("t:{}", t)).collect();
+        terms.extend(self.url_host.iter().map(|h| format!("h:{}", h)));
+        terms.extend(self.url_path.iter().map(|p| format!("p:{}", p)));
+        terms.extend(self.extension.iter().map(|e| format!("e:{}", e)));
+        terms
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 2)
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// How often each term was seen among files sent to one destination.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelStats {
+    pub examples: u32,
+    pub term_counts: BTreeMap<String, u32>,
+    pub total_terms: u32,
+}
+
+/// A multinomial naive Bayes model over review decisions, keyed by destination directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub trained_at: DateTime<Local>,
+    pub examples: usize,
+    /// Rejected suggestions seen while training. They carry no destination to learn.
+    pub rejected: usize,
+    pub labels: BTreeMap<PathBuf, LabelStats>,
+    pub vocabulary: usize,
+}
+
+const MODEL_FILE_NAME: &str = "model.json";
+
+impl Model {
+    /// Trains on every suggestion whose last decision was an accept, using the
+    /// destination it was accepted with. Redirects that were later rejected, snoozed or
+    /// never applied teach nothing, and neither do renames that left the file in its
+    /// folder, which would teach the model to send files back where they came from.
+    pub fn train(decisions: &[Decision]) -> Model {
+        let mut last: HashMap<u64, &Decision> = HashMap::new();
+        for decision in decisions {
+            last.insert(decision.queue_id, decision);
+        }
+        let rejected = last
+            .values()
+            .filter(|decision| decision.action == Action::Reject)
+            .count();
+        let accepted: Vec<(&Decision, &PathBuf)> = last
+            .values()
+            .filter(|decision| decision.action == Action::Accept)
+            .filter_map(|decision| Some((*decision, decision.chosen_destination.as_ref()?)))
+            .filter(|(decision, destination)| decision.source_dir.as_ref() != Some(*destination))
+            .collect();
+
+        let mut labels: BTreeMap<PathBuf, LabelStats> = BTreeMap::new();
+        let mut vocabulary = HashSet::new();
+        for (decision, destination) in &accepted {
+            let features = decision.features.clone().unwrap_or_else(|| {
+                Features::extract(decision.file_name.as_deref().unwrap_or_default(), None)
+            });
+            let stats = labels.entry(destination.to_path_buf()).or_default();
+            stats.examples += 1;
+            for term in features.terms() {
+                *stats.term_counts.entry(term.clone()).or_default() += 1;
+                stats.total_terms += 1;
+                vocabulary.insert(term);
+            }
+        }
+        Model {
+            trained_at: Local::now(),
+            examples: accepted.len(),
+            rejected,
+            labels,
+            vocabulary: vocabulary.len(),
+        }
+    }
+
+    /// Destinations ranked by posterior probability, best first.
+    pub fn rank(&self, features: &Features) -> Vec<(PathBuf, f32)> {
+        if self.examples == 0 {
+            return Vec::new();
+        }
+        let terms: Vec<String> = features
+            .terms()
+            .into_iter()
+            .filter(|term| self.is_known(term))
+            .collect();
+        let vocabulary = self.vocabulary.max(1) as f64;
+        let scores: Vec<(PathBuf, f64)> = self
+            .labels
+            .iter()
+            .map(|(label, stats)| {
+                let prior = (stats.examples as f64 / self.examples as f64).ln();
+                let likelihood: f64 = terms
+                    .iter()
+                    .map(|term| {
+                        let count = stats.term_counts.get(term).copied().unwrap_or(0) as f64;
+                        ((count + 1.0) / (stats.total_terms as f64 + vocabulary)).ln()
+                    })
+                    .sum();
+                (label.clone(), prior + likelihood)
+            })
+            .collect();
+        // Softmax over the log scores, shifted by the max to stay in range.
+        let max = scores
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let total: f64 = scores.iter().map(|(_, score)| (score - max).exp()).sum();
+        let mut ranked: Vec<(PathBuf, f32)> = scores
+            .into_iter()
+            .map(|(label, score)| (label, ((score - max).exp() / total) as f32))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked
+    }
+
+    fn is_known(&self, term: &str) -> bool {
+        self.labels
+            .values()
+            .any(|stats| stats.term_counts.contains_key(term))
+    }
+
+    pub fn load(dir: &Path) -> io::Result<Option<Model>> {
+        let contents = match fs::read_to_string(model_path(dir)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the model beside the queue, replacing the old one atomically.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = model_path(dir);
+        let tmp_path = dir.join(format!("{}.tmp", MODEL_FILE_NAME));
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+pub fn model_path(dir: &Path) -> PathBuf {
+    dir.join(MODEL_FILE_NAME)
+}
+
+pub fn train_model() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = data_dir()?;
+    let decisions = DecisionLog::open(&dir)?.all()?;
+    let model = Model::train(&decisions);
+    model.save(&dir)?;
+    println!(
+        "Trained on {} reviewed suggestion(s) across {} destination(s); saved to {}",
+        model.examples,
+        model.labels.len(),
+        model_path(&dir).display()
+    );
+    Ok(())
+}
+
+pub fn show_stats(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = data_dir()?;
+    let model = Model::load(&dir)?.ok_or("No model yet. Run `nimbus train` first")?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&model)?);
+        return Ok(());
+    }
+    println!(
+        "Trained {} on {} reviewed suggestion(s), {} rejected, {} distinct terms",
+        model.trained_at.format("%Y-%m-%d %H:%M"),
+        model.examples,
+        model.rejected,
+        model.vocabulary
+    );
+    println!("{:>8}  {:<50}  TOP TERMS", "EXAMPLES", "DESTINATION");
+    for (label, stats) in &model.labels {
+        let mut terms: Vec<(&String, &u32)> = stats.term_counts.iter().collect();
+        terms.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let top: Vec<&str> = terms.iter().take(5).map(|(t, _)| t.as_str()).collect();
+        println!(
+            "{:>8}  {:<50}  {}",
+            stats.examples,
+            label.display(),
+            top.join(" ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decision(queue_id: u64, action: Action, name: &str, destination: &str) -> Decision {
+        Decision {
+            queue_id,
+            decided_at: Local::now(),
+            action,
+            file_name: Some(name.to_string()),
+            source_dir: Some(PathBuf::from("/home/me/Downloads")),
+            suggested_destination: Some(PathBuf::from(destination)),
+            chosen_destination: Some(PathBuf::from(destination)),
+            course: None,
+            new_name: None,
+            features: Some(Features::extract(name, None)),
+        }
+    }
+
+    fn best(model: &Model, name: &str) -> Option<PathBuf> {
+        let ranked = model.rank(&Features::extract(name, None));
+        ranked
+            .into_iter()
+            .next()
+            .map(|(destination, _)| destination)
+    }
+
+    #[test]
+    fn extracts_words_host_path_and_extension() {
+        let features = Features::extract(
+            "Receipt_2024-01.PDF",
+            Some("https://shop.example.com/orders/invoice"),
+        );
+        assert_eq!(features.tokens, ["receipt", "2024", "01"]);
+        assert_eq!(features.url_host.as_deref(), Some("shop.example.com"));
+        assert_eq!(features.url_path, ["orders", "invoice"]);
+        assert_eq!(features.extension.as_deref(), Some("pdf"));
+    }
+
+    #[test]
+    fn ranks_the_destination_with_similar_names_first() {
+        let model = Model::train(&[
+            decision(1, Action::Accept, "receipt jan.pdf", "/base/Finance"),
+            decision(2, Action::Accept, "receipt feb.pdf", "/base/Finance"),
+            decision(3, Action::Accept, "resume draft.docx", "/base/Career"),
+            decision(4, Action::Accept, "cover letter.docx", "/base/Career"),
+        ]);
+        assert_eq!(model.examples, 4);
+        assert_eq!(
+            best(&model, "receipt mar.pdf"),
+            Some("/base/Finance".into())
+        );
+        assert_eq!(
+            best(&model, "resume final.docx"),
+            Some("/base/Career".into())
+        );
+
+        let ranked = model.rank(&Features::extract("receipt mar.pdf", None));
+        let total: f32 = ranked.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-4, "probabilities sum to {}", total);
+        assert!(ranked[0].1 > ranked[1].1);
+    }
+
+    #[test]
+    fn learns_only_the_last_decision_per_suggestion() {
+        let mut redirected = decision(1, Action::CustomDestination, "receipt.pdf", "/base/Tax");
+        redirected.suggested_destination = Some("/base/Finance".into());
+        let model = Model::train(&[
+            redirected,
+            decision(1, Action::Accept, "receipt.pdf", "/base/Tax"),
+            decision(2, Action::CustomDestination, "notes.pdf", "/base/Notes"),
+            decision(2, Action::Reject, "notes.pdf", "/base/Notes"),
+            decision(3, Action::ChangeCourse, "slides.pdf", "/base/CS246"),
+        ]);
+        assert_eq!(model.examples, 1);
+        assert_eq!(model.rejected, 1);
+        assert_eq!(
+            model.labels.keys().collect::<Vec<_>>(),
+            [Path::new("/base/Tax")]
+        );
+    }
+
+    #[test]
+    fn ignores_renames_that_left_the_file_in_place() {
+        let model = Model::train(&[
+            decision(1, Action::Accept, "scan 1.pdf", "/home/me/Downloads"),
+            decision(2, Action::Accept, "scan 2.pdf", "/base/Scans"),
+        ]);
+        assert_eq!(model.examples, 1);
+        assert!(!model.labels.contains_key(Path::new("/home/me/Downloads")));
+    }
+
+    #[test]
+    fn untrained_model_ranks_nothing() {
+        let model = Model::train(&[]);
+        assert!(model
+            .rank(&Features::extract("receipt.pdf", None))
+            .is_empty());
+    }
+}
@@ -1,125 +1,1077 @@
-use crate::review::{write_command, Command, CommandEnum, ReasonEnum};
+use crate::auto_apply::{should_auto_apply, AutoApply};
+use crate::classifier::{
+    classify, Classifier, CourseCodeClassifier, LlmClassifier, ModelClassifier, Placement,
+};
+use crate::control::{recent_errors, DaemonLock, DaemonStatus, Request, Response};
+use crate::duplicates::{versioned_name, ContentIndex};
+use crate::executor::MoveExecutor;
+use crate::extract::{extract_text, ExtractionConfig};
+use crate::journal::{hash_file, AppliedBy, Journal};
+use crate::llm::{ChatClient, LlmConfig, LEGACY_API_KEY_ENV};
+use crate::naming::{propose_name, NameContext};
+use crate::origin::{default_providers, read_origin, OriginProvider};
+use crate::queue::{FileIdentity, Queue, QueueEntry, Status};
+use crate::readiness::Readiness;
+use crate::review::{apply_entry, display_target, executor_for, Command, CommandEnum, ReasonEnum};
+use crate::roots::{compile_roots, root_for, CompiledRoot, WatchRoot};
+use crate::rules::RuleSet;
 
-use crate::setup::{read_config, Config, Course};
-use chatgpt::prelude::*;
-use notify::event::{CreateKind, EventKind, ModifyKind};
-use notify::{Error, Event, FsEventWatcher};
+use crate::setup::{data_dir, read_config, Config};
+use crate::subfolders::Categorizer;
+use chrono::{DateTime, Local, NaiveDate};
+use notify::event::{CreateKind, DataChange, EventKind, ModifyKind, RenameMode};
+use notify::RecommendedWatcher;
 use notify::{RecursiveMode, Watcher};
-use notify_debouncer_full::{new_debouncer, DebouncedEvent, Debouncer, FileIdMap};
-use plist::Value;
-use std::collections::HashMap;
-use std::io;
+use notify_debouncer_full::{
+    new_debouncer_opt, DebounceEventHandler, DebounceEventResult, DebouncedEvent, Debouncer,
+    FileIdMap,
+};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::{
     path::Path,
     sync::mpsc::{channel, Sender},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use tokio::*;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::oneshot;
 
 #[derive(Debug, Clone)]
 pub struct File {
-    name: String,
-    url: String,
-    path: PathBuf,
+    pub name: String,
+    pub url: Option<String>,
+    pub referrer: Option<String>,
+    pub path: PathBuf,
+    /// Leading text of the file's contents, when it is a document nimbus can read.
+    pub text: Option<String>,
 }
 
-enum ParseEventResult {
-    File(File),
-    Empty,
-}
-enum GrepCourseCodeResult {
-    Course(Course),
-    Empty,
+pub fn start_monitor(config_path: &Path) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // FSEvents on macOS, inotify on Linux.
+    start_monitor_with::<RecommendedWatcher>(config_path)
 }
 
-pub fn start_monitor() -> std::result::Result<(), Box<dyn std::error::Error>> {
+pub fn start_monitor_with<W: Watcher>(
+    config_path: &Path,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
     log::info!("Starting monitor...");
-    let gpt_client =
-        ChatGPT::new(std::env::var("GPT_API_KEY").expect("OpenAI API key required")).unwrap();
-    let mut conversation: Conversation = gpt_client.new_conversation_directed(
-        "You are a LLM designed to categorize downloaded files into their  ",
-    );
+    let lock = DaemonLock::acquire(&data_dir()?)?;
+    let config = read_config(config_path)?;
     let (action_tx, mut action_rx) = tokio::sync::mpsc::unbounded_channel();
-    let config = read_config()?;
-    let directory_map = create_directory_map(&config)?;
-    let courses = config.courses;
-    let download_path = config.download_path;
-    log::info!("Starting monitor on {}", download_path.to_str().unwrap());
-    let (debouncer_tx, debouncer_rx) = channel();
+    let (message_tx, message_rx) = channel();
+    let state = Arc::new(RunState::default());
+    let daemon = Arc::new(Daemon {
+        pipeline: RwLock::new(Arc::new(Pipeline::new(&config)?.with_state(state.clone()))),
+        state,
+        started_at: Local::now(),
+        messages: message_tx.clone(),
+    });
 
-    let _debouncer =
-        create_debouncer(download_path, debouncer_tx.clone()).expect("Failed to create debouncer");
+    let signals = Signals::register()?;
+    tokio::spawn(signals.handle(message_tx.clone()));
 
+    let events_tx = message_tx;
+    let mut debouncer = create_debouncer::<W, _>(move |result| {
+        let _ = events_tx.send(Message::Events(result));
+    })
+    .expect("Failed to create debouncer");
+    let mut watched: Vec<WatchRoot> = daemon
+        .pipeline()
+        .watch_roots()
+        .into_iter()
+        .filter(|root| watch_root(&mut debouncer, root))
+        .collect();
+    // Editing the config reloads it without a restart. Editors replace the file instead
+    // of writing to it, so watch the folder it is in.
+    let config_file = std::fs::canonicalize(config_path)?;
+    if let Some(config_dir) = config_file.parent() {
+        debouncer
+            .watcher()
+            .watch(config_dir, RecursiveMode::NonRecursive)?;
+    }
+
+    let listener = UnixListener::bind(lock.socket_path())?;
+    tokio::spawn(serve(listener, daemon.clone()));
+
+    // Catch up on files that arrived while nimbus was not running. Events that come in
+    // meanwhile are handled as usual.
+    let startup = daemon.pipeline();
+    let startup_roots = watched.clone();
     tokio::spawn(async move {
+        let scanned = startup.scan(&startup_roots, None, true).await;
+        log::info!("Startup scan found {} file(s)", scanned);
+    });
+
+    let events = daemon.clone();
+    let event_task = tokio::spawn(async move {
         while let Some(event) = action_rx.recv().await {
-            let file = parse_event(event);
-            if let Ok(ParseEventResult::File(file)) = file {
-                log::info!("File: {:?}", file);
-                match grep_course_code(&file, courses.clone()) {
-                    Ok(GrepCourseCodeResult::Course(_course)) => {
-                        let directory = directory_map.get(&_course.name).unwrap();
-                        let command: Command = Command {
-                            file_path: Some(file.path.clone()),
-                            command: CommandEnum::Move,
-                            destination: Some(directory.to_path_buf()), // TODO: Add destination.
-                            reason: Some(ReasonEnum::CourseCode),
-                        };
-                        log::info!("Saving command: {:?}", command);
-                        write_command(command).unwrap();
-                    }
-                    Ok(GrepCourseCodeResult::Empty) => {
-                        let contents = get_file_contents(file.path.to_str().unwrap());
-                        match contents {
-                            Ok(contents) => {}
-                            Err(e) => {
-                                let command: Command = Command {
-                                    file_path: (None),
-                                    command: (CommandEnum::Indeterminate),
-                                    destination: (None),
-                                    reason: (None),
-                                };
-                            }
+            events.pipeline().handle(&event);
+        }
+    });
+
+    for message in message_rx.iter() {
+        match message {
+            Message::Events(Ok(events)) => {
+                for event in events {
+                    if event.event.paths.contains(&config_file) {
+                        if let Err(e) = reload(config_path, &daemon, &mut debouncer, &mut watched) {
+                            log::error!("Keeping the current config: {}", e);
                         }
+                        continue;
                     }
-                    Err(e) => {
-                        log::error!("Error: {}", e);
-                    }
+                    action_tx.send(event).unwrap();
+                }
+            }
+            Message::Events(Err(errors)) => {
+                errors.iter().for_each(|error| log::error!("{error:?}"))
+            }
+            Message::Reload(reply) => {
+                let result = reload(config_path, &daemon, &mut debouncer, &mut watched);
+                if let Err(e) = &result {
+                    log::error!("Keeping the current config: {}", e);
+                }
+                let _ = reply.send(result);
+            }
+            Message::Stop => {
+                log::info!("Stopping monitor");
+                break;
+            }
+        }
+    }
+
+    // Stop watching, then let the events already received and the suggestions being made
+    // finish, so nothing is cut off halfway through writing the queue or moving a file.
+    // Files still downloading are picked up by the startup scan next time.
+    daemon.state.stopping.store(true, Ordering::SeqCst);
+    debouncer.stop();
+    let busy = drain(
+        &daemon.state,
+        message_rx.try_iter(),
+        &config_file,
+        action_tx,
+        &event_task,
+        SHUTDOWN_TIMEOUT,
+    );
+    let pipeline = daemon.pipeline();
+    pipeline.queue.flush()?;
+    pipeline.journal.flush()?;
+    if busy > 0 {
+        return Err(format!(
+            "Gave up after {}s on {} file(s) still being processed",
+            SHUTDOWN_TIMEOUT.as_secs(),
+            busy
+        )
+        .into());
+    }
+    log::info!("Shut down cleanly");
+    Ok(())
+}
+
+/// Hands the events received before the watcher stopped to the event task, then waits up
+/// to `timeout` for it and for the files being processed. Returns how many files were
+/// still being processed when it gave up.
+fn drain(
+    state: &RunState,
+    received: impl Iterator<Item = Message>,
+    config_file: &Path,
+    action_tx: tokio::sync::mpsc::UnboundedSender<DebouncedEvent>,
+    event_task: &tokio::task::JoinHandle<()>,
+    timeout: Duration,
+) -> usize {
+    for message in received {
+        if let Message::Events(Ok(events)) = message {
+            for event in events {
+                if !event.event.paths.iter().any(|path| path == config_file) {
+                    let _ = action_tx.send(event);
+                }
+            }
+        }
+    }
+    drop(action_tx);
+    let deadline = std::time::Instant::now() + timeout;
+    let mut busy = state.processing.load(Ordering::SeqCst);
+    while !event_task.is_finished() || busy > 0 {
+        if std::time::Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        busy = state.processing.load(Ordering::SeqCst);
+    }
+    busy
+}
+
+/// How long shutting down waits for files being processed. launchd kills the daemon 20
+/// seconds after asking it to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Flags the daemon shares with every pipeline it builds, so a reload keeps them.
+#[derive(Default)]
+struct RunState {
+    paused: AtomicBool,
+    stopping: AtomicBool,
+    /// Files between being picked up and being queued or applied.
+    processing: AtomicUsize,
+}
+
+/// Counts a file as being processed until dropped.
+struct Processing<'a>(&'a AtomicUsize);
+
+impl<'a> Processing<'a> {
+    fn start(count: &'a AtomicUsize) -> Processing<'a> {
+        count.fetch_add(1, Ordering::SeqCst);
+        Processing(count)
+    }
+}
+
+impl Drop for Processing<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The signals the daemon handles. Registering them replaces the default of exiting
+/// right away.
+struct Signals {
+    terminate: Signal,
+    interrupt: Signal,
+    hangup: Signal,
+}
+
+impl Signals {
+    fn register() -> std::io::Result<Signals> {
+        Ok(Signals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    /// SIGTERM and SIGINT shut the monitor down and SIGHUP reloads the config. A second
+    /// SIGTERM or SIGINT exits without waiting, with the usual 128 + signal status.
+    async fn handle(mut self, messages: Sender<Message>) {
+        let mut stopping = false;
+        loop {
+            let received = tokio::select! {
+                _ = self.terminate.recv() => Received::Terminate,
+                _ = self.interrupt.recv() => Received::Interrupt,
+                _ = self.hangup.recv() => Received::Hangup,
+            };
+            if let Some(status) = received.handle(&mut stopping, &messages) {
+                std::process::exit(status);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Received {
+    Terminate,
+    Interrupt,
+    Hangup,
+}
+
+impl Received {
+    /// Tells the watcher thread what to do about the signal. Returns the status to exit
+    /// with right away, for a second SIGTERM or SIGINT.
+    fn handle(self, stopping: &mut bool, messages: &Sender<Message>) -> Option<i32> {
+        let (name, status) = match self {
+            Received::Terminate => ("SIGTERM", 128 + 15),
+            Received::Interrupt => ("SIGINT", 128 + 2),
+            Received::Hangup => {
+                log::info!("Received SIGHUP, reloading the config");
+                let (reply, _) = oneshot::channel();
+                let _ = messages.send(Message::Reload(reply));
+                return None;
+            }
+        };
+        if *stopping {
+            log::warn!("Received {} again, exiting without waiting", name);
+            return Some(status);
+        }
+        log::info!("Received {}, shutting down", name);
+        *stopping = true;
+        let _ = messages.send(Message::Stop);
+        None
+    }
+}
+
+/// What the thread that owns the watcher reacts to.
+enum Message {
+    Events(DebounceEventResult),
+    Reload(oneshot::Sender<std::result::Result<(), String>>),
+    Stop,
+}
+
+/// The running monitor. A reload swaps in a new pipeline built from the new config.
+struct Daemon {
+    pipeline: RwLock<Arc<Pipeline>>,
+    state: Arc<RunState>,
+    started_at: DateTime<Local>,
+    messages: Sender<Message>,
+}
+
+impl Daemon {
+    fn pipeline(&self) -> Arc<Pipeline> {
+        self.pipeline.read().unwrap().clone()
+    }
+
+    async fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Status => {
+                let pipeline = self.pipeline();
+                let pending = match pipeline.queue.pending() {
+                    Ok(pending) => pending.len(),
+                    Err(e) => return Response::failed(format!("Failed to read the queue: {}", e)),
+                };
+                let settling = pipeline.waiting.lock().unwrap().len();
+                Response {
+                    ok: true,
+                    message: None,
+                    status: Some(DaemonStatus {
+                        pid: std::process::id(),
+                        started_at: self.started_at,
+                        paused: self.state.paused.load(Ordering::SeqCst),
+                        roots: pipeline
+                            .watch_roots()
+                            .into_iter()
+                            .map(|root| root.path)
+                            .collect(),
+                        pending,
+                        settling,
+                        recent_errors: recent_errors(),
+                    }),
+                }
+            }
+            Request::Pause => {
+                self.state.paused.store(true, Ordering::SeqCst);
+                log::info!("Paused");
+                Response::done("Paused. New files will be looked at after `nimbus resume`")
+            }
+            Request::Resume => {
+                if !self.state.paused.swap(false, Ordering::SeqCst) {
+                    return Response::done("Not paused");
+                }
+                log::info!("Resumed");
+                let pipeline = self.pipeline();
+                tokio::spawn(async move {
+                    let roots = pipeline.watch_roots();
+                    let scanned = pipeline.scan(&roots, None, true).await;
+                    log::info!("Found {} file(s) that arrived while paused", scanned);
+                });
+                Response::done("Resumed")
+            }
+            Request::Reload => {
+                let (reply, result) = oneshot::channel();
+                if self.messages.send(Message::Reload(reply)).is_err() {
+                    return Response::failed("The monitor is shutting down".to_string());
                 }
-            } else if let Err(e) = file {
-                log::error!("Error: {}", e);
+                match result.await {
+                    Ok(Ok(())) => Response::done("Reloaded the config"),
+                    Ok(Err(e)) => Response::failed(format!("Kept the current config: {}", e)),
+                    Err(_) => Response::failed("The monitor is shutting down".to_string()),
+                }
+            }
+            Request::Stop => {
+                let _ = self.messages.send(Message::Stop);
+                Response::done("Stopping")
+            }
+        }
+    }
+}
+
+/// Answers requests on the control socket, one JSON line in and one out per connection.
+async fn serve(listener: UnixListener, daemon: Arc<Daemon>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::error!("Control socket failed: {}", e);
+                return;
+            }
+        };
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut line = String::new();
+            if let Err(e) = tokio::io::BufReader::new(reader).read_line(&mut line).await {
+                log::warn!("Could not read a control request: {}", e);
+                return;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => daemon.handle(request).await,
+                Err(e) => Response::failed(format!("Invalid request: {}", e)),
+            };
+            let Ok(mut reply) = serde_json::to_string(&response) else {
+                return;
+            };
+            reply.push('\n');
+            if let Err(e) = writer.write_all(reply.as_bytes()).await {
+                log::warn!("Could not answer a control request: {}", e);
             }
+        });
+    }
+}
+
+/// Rebuilds the pipeline from the config and brings the watched folders in line with it.
+/// On error the current pipeline keeps running.
+fn reload<W: Watcher>(
+    config_path: &Path,
+    daemon: &Daemon,
+    debouncer: &mut Debouncer<W, FileIdMap>,
+    watched: &mut Vec<WatchRoot>,
+) -> std::result::Result<(), String> {
+    let config = read_config(config_path).map_err(|e| e.to_string())?;
+    let pipeline = Pipeline::new(&config).map_err(|e| e.to_string())?;
+    let waiting = daemon.pipeline.read().unwrap().waiting.clone();
+    let pipeline = Arc::new(
+        pipeline
+            .with_state(daemon.state.clone())
+            .with_waiting(waiting),
+    );
+    let wanted = pipeline.watch_roots();
+    for root in watched.iter().filter(|root| !wanted.contains(root)) {
+        log::info!("No longer watching {}", root.path.display());
+        let _ = debouncer.watcher().unwatch(&root.path);
+        debouncer.cache().remove_root(&root.path);
+    }
+    let added: Vec<WatchRoot> = wanted
+        .iter()
+        .filter(|root| !watched.contains(root))
+        .filter(|root| watch_root(debouncer, root))
+        .cloned()
+        .collect();
+    watched.retain(|root| wanted.contains(root));
+    watched.extend(added.iter().cloned());
+    *daemon.pipeline.write().unwrap() = pipeline.clone();
+    log::info!("Reloaded {}", config_path.display());
+    tokio::spawn(async move {
+        // `base_path` may have changed; scanning refreshes the index too.
+        if added.is_empty() {
+            pipeline.refresh_index().await;
+            return;
         }
+        let scanned = pipeline.scan(&added, None, true).await;
+        log::info!("Scan of new watch roots found {} file(s)", scanned);
     });
+    Ok(())
+}
+
+/// Starts watching a root. Failures are logged so the other roots keep working.
+fn watch_root<W: Watcher>(debouncer: &mut Debouncer<W, FileIdMap>, root: &WatchRoot) -> bool {
+    let mode = if root.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    if let Err(e) = debouncer.watcher().watch(&root.path, mode) {
+        log::error!("Failed to watch {}: {}", root.path.display(), e);
+        return false;
+    }
+    debouncer.cache().add_root(&root.path, mode);
+    log::info!("Watching {}", root.path.display());
+    true
+}
+
+/// Suggests destinations for the files already in the watched folders, for `nimbus scan`.
+pub async fn scan_downloads(
+    config_path: &Path,
+    since: Option<NaiveDate>,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let config = read_config(config_path)?;
+    let since = match since {
+        Some(date) => Some(
+            date.and_hms_opt(0, 0, 0)
+                .and_then(|time| time.and_local_timezone(Local).earliest())
+                .ok_or(format!("Invalid date {}", date))?
+                .into(),
+        ),
+        None => None,
+    };
+    let pipeline = Arc::new(Pipeline::new(&config)?);
+    let roots = pipeline.watch_roots();
+    let scanned = pipeline.scan(&roots, since, false).await;
+    let paths: Vec<String> = roots
+        .iter()
+        .map(|root| root.path.display().to_string())
+        .collect();
+    println!("Scanned {} new file(s) in {}", scanned, paths.join(", "));
+    Ok(())
+}
+
+/// Turns a finished download into a queued suggestion.
+struct Pipeline {
+    readiness: Readiness,
+    origin_providers: Vec<Box<dyn OriginProvider>>,
+    extraction: ExtractionConfig,
+    rules: RuleSet,
+    classifiers: Vec<Box<dyn Classifier>>,
+    categorizer: Categorizer,
+    directory_map: HashMap<String, PathBuf>,
+    rename_template: Option<String>,
+    queue: Queue,
+    journal: Journal,
+    executor: MoveExecutor,
+    auto_apply: Vec<AutoApply>,
+    content_index: Arc<Mutex<ContentIndex>>,
+    base_path: PathBuf,
+    data_dir: PathBuf,
+    roots: Vec<CompiledRoot>,
+    /// Shared with the daemon, which can pause or stop the pipeline.
+    state: Arc<RunState>,
+    /// Paths that already have a task waiting for them to settle. Handed on to the
+    /// pipeline that replaces this one on reload, since those tasks keep running.
+    waiting: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl Pipeline {
+    fn new(config: &Config) -> std::result::Result<Pipeline, Box<dyn std::error::Error>> {
+        Pipeline::open(config, &data_dir()?)
+    }
+
+    /// A pipeline keeping its queue, journal and index in `data_dir`.
+    fn open(
+        config: &Config,
+        data_dir: &Path,
+    ) -> std::result::Result<Pipeline, Box<dyn std::error::Error>> {
+        let directory_map = create_directory_map(config)?;
+        Ok(Pipeline {
+            readiness: Readiness::new(&config.readiness)?,
+            origin_providers: default_providers(),
+            extraction: config.extraction.clone(),
+            rules: RuleSet::new(&config.rules, &config.base_path)?,
+            classifiers: create_classifiers(config, &directory_map, data_dir)?,
+            categorizer: Categorizer::new(config)?,
+            directory_map,
+            rename_template: config.rename_template.clone(),
+            queue: Queue::open(data_dir)?,
+            journal: Journal::open(data_dir)?,
+            executor: executor_for(Some(config)),
+            auto_apply: config.auto_apply.clone(),
+            content_index: Arc::new(Mutex::new(ContentIndex::load(data_dir))),
+            base_path: PathBuf::from(&config.base_path),
+            data_dir: data_dir.to_path_buf(),
+            roots: compile_roots(config)?,
+            state: Arc::default(),
+            waiting: Arc::default(),
+        })
+    }
+
+    fn with_state(mut self, state: Arc<RunState>) -> Pipeline {
+        self.state = state;
+        self
+    }
+
+    fn with_waiting(mut self, waiting: Arc<Mutex<HashSet<PathBuf>>>) -> Pipeline {
+        self.waiting = waiting;
+        self
+    }
 
-    for result in debouncer_rx {
+    fn watch_roots(&self) -> Vec<WatchRoot> {
+        self.roots.iter().map(|root| root.root.clone()).collect()
+    }
+
+    /// Partial and hidden files, files in partial or hidden folders, files outside every
+    /// root, and files the root ignores.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(root) = root_for(&self.roots, path) else {
+            return true;
+        };
+        self.readiness.is_ignored(path, &root.root.path) || root.ignores(path)
+    }
+
+    /// The classifiers the file's watch root lets place it, in order.
+    fn classifiers_for(&self, path: &Path) -> Vec<&dyn Classifier> {
+        let root = root_for(&self.roots, path);
+        self.classifiers
+            .iter()
+            .map(|classifier| classifier.as_ref())
+            .filter(|classifier| root.is_none_or(|root| root.allows(classifier.name())))
+            .collect()
+    }
+
+    /// Reacts to one filesystem event: follows renames, invalidates suggestions for
+    /// removed files and watches written ones until they settle.
+    fn handle(self: &Arc<Self>, event: &DebouncedEvent) {
+        let path = match file_change(event) {
+            Some(FileChange::Written(path)) => path,
+            Some(FileChange::Renamed(from, to)) => {
+                if self.is_ignored(&to) {
+                    self.invalidate(&from);
+                    return;
+                }
+                self.retarget(&from, &to);
+                to
+            }
+            Some(FileChange::Removed(path)) => {
+                self.invalidate(&path);
+                return;
+            }
+            None => return,
+        };
+        if self.is_ignored(&path) {
+            log::debug!("Ignoring {}", path.display());
+            return;
+        }
+        self.watch(path);
+    }
+
+    /// Waits in the background for `path` to settle, then suggests a destination for it.
+    /// Does nothing if `path` is already being waited on.
+    fn watch(self: &Arc<Self>, path: PathBuf) {
+        if self.state.stopping.load(Ordering::SeqCst)
+            || !self.waiting.lock().unwrap().insert(path.clone())
+        {
+            return;
+        }
+        let pipeline = self.clone();
+        tokio::spawn(async move {
+            if pipeline.readiness.wait_until_ready(&path).await {
+                pipeline.process(&path).await;
+            }
+            pipeline.waiting.lock().unwrap().remove(&path);
+        });
+    }
+
+    /// Brings the content index up to date, then suggests destinations for files in
+    /// `roots` that were never suggested, one at a time. Files still being written are
+    /// watched until they settle if `watch_unsettled` is set, and skipped otherwise.
+    /// Returns how many files were taken up.
+    async fn scan(
+        self: &Arc<Self>,
+        roots: &[WatchRoot],
+        since: Option<SystemTime>,
+        watch_unsettled: bool,
+    ) -> usize {
+        self.refresh_index().await;
+        let mut backlog = Vec::new();
+        for root in roots {
+            match self.backlog(root, since) {
+                Ok(files) => backlog.extend(files),
+                Err(e) => log::error!("Failed to scan {}: {}", root.path.display(), e),
+            }
+        }
+        let mut scanned = 0;
+        for path in backlog {
+            if !self.readiness.is_settled(&path) {
+                if watch_unsettled {
+                    self.watch(path);
+                    scanned += 1;
+                } else {
+                    log::info!("Skipping {}, it is still being written", path.display());
+                }
+                continue;
+            }
+            if !self.waiting.lock().unwrap().insert(path.clone()) {
+                continue;
+            }
+            self.process(&path).await;
+            self.waiting.lock().unwrap().remove(&path);
+            scanned += 1;
+        }
+        scanned
+    }
+
+    /// Files in `root` modified since `since` that are not ignored and have never been
+    /// suggested, or whose suggestion was invalidated or failed.
+    fn backlog(
+        &self,
+        root: &WatchRoot,
+        since: Option<SystemTime>,
+    ) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut pending = vec![root.path.clone()];
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    if root.recursive && !self.readiness.is_ignored(&path, &root.path) {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                let modified = entry.metadata()?.modified()?;
+                if !file_type.is_file() || since.is_some_and(|since| modified < since) {
+                    continue;
+                }
+                if self.queue.was_suggested(&path, FileIdentity::of(&path))? {
+                    log::debug!("{} was already suggested", path.display());
+                    continue;
+                }
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    async fn process(&self, path: &Path) {
+        // Counted before checking `stopping`, so shutdown either waits for this file or
+        // it is left alone.
+        let _processing = Processing::start(&self.state.processing);
+        if self.state.stopping.load(Ordering::SeqCst) {
+            log::info!(
+                "Shutting down, leaving {} for the next start",
+                path.display()
+            );
+            return;
+        }
+        if self.state.paused.load(Ordering::SeqCst) {
+            log::info!("Paused, leaving {} for later", path.display());
+            return;
+        }
+        let Some(file_name) = path.to_str().and_then(extract_filename) else {
+            log::error!("Invalid path {}", path.display());
+            return;
+        };
+        // The browser sets the origin xattrs on the final file, so read them only now.
+        let origin = read_origin(&self.origin_providers, path);
+        let mut file = File {
+            name: file_name.to_string(),
+            url: origin.url,
+            referrer: origin.referrer,
+            path: path.to_path_buf(),
+            text: None,
+        };
+        file.text = read_text(&file.path, &self.extraction).await;
+        log::info!("File: {} ({:?})", file.name, file.url);
+        let hash = self.content_hash(path).await;
+        let duplicate_of = hash.as_deref().and_then(|hash| {
+            let mut index = self.content_index.lock().unwrap();
+            index.reload_if_changed(&self.data_dir);
+            index.find_hash(hash, path).map(Path::to_path_buf)
+        });
+        let command = match duplicate_of {
+            Some(existing) => {
+                log::info!("{} is already filed as {}", file.name, existing.display());
+                Command {
+                    file_path: Some(file.path.clone()),
+                    command: CommandEnum::Duplicate,
+                    destination: Some(existing),
+                    reason: Some(ReasonEnum::Duplicate),
+                    rename: None,
+                    confidence: Some(1.0),
+                    tags: Vec::new(),
+                }
+            }
+            None => {
+                let Some(mut command) = self.place(&file).await else {
+                    return;
+                };
+                if hash.is_some() {
+                    self.version(&mut command, &file.name);
+                }
+                command
+            }
+        };
+        // Every write to the file produces events; they all update one suggestion.
+        let entry = match self.queue.suggest(command, FileIdentity::of(path)) {
+            Ok((entry, true)) => {
+                log::info!("Queued suggestion #{}: {:?}", entry.id, entry.command);
+                entry
+            }
+            Ok((entry, false)) => {
+                log::info!("Updated suggestion #{}: {:?}", entry.id, entry.command);
+                entry
+            }
+            Err(e) => {
+                log::error!("Failed to queue suggestion: {}", e);
+                return;
+            }
+        };
+        // A reviewer who edited the suggestion but left it for later still decides it.
+        if entry.status == Status::Pending
+            && !entry.edited
+            && self.should_auto_apply(&entry.command, &file, hash.as_deref())
+        {
+            self.auto_apply(&entry, hash);
+        }
+    }
+
+    /// The root's own policies if it has any, otherwise the config-wide ones. A file the
+    /// user already moved back with `nimbus undo`, or one that could not be hashed to
+    /// tell, always goes to review instead.
+    fn should_auto_apply(&self, command: &Command, file: &File, hash: Option<&str>) -> bool {
+        let Some(hash) = hash else {
+            return false;
+        };
+        match self.journal.was_undone(&file.path, hash) {
+            Ok(false) => {}
+            Ok(true) => return false,
+            Err(e) => {
+                log::error!("Failed to read the journal: {}", e);
+                return false;
+            }
+        }
+        let policies = root_for(&self.roots, &file.path)
+            .and_then(|root| root.root.auto_apply.as_ref())
+            .unwrap_or(&self.auto_apply);
+        should_auto_apply(policies, command, file)
+    }
+
+    /// Applies a suggestion without review. It is journaled like any other, so
+    /// `nimbus history` lists it and `nimbus undo` reverts it. `hash` is the file's
+    /// content hash, for indexing it where it was filed.
+    fn auto_apply(&self, entry: &QueueEntry, hash: Option<String>) {
+        match apply_entry(
+            &self.queue,
+            &self.journal,
+            &self.executor,
+            entry,
+            AppliedBy::Auto,
+        ) {
+            Ok((applied, destination)) if applied.status == Status::Accepted => {
+                log::info!(
+                    "Auto-applied suggestion #{}: {}",
+                    applied.id,
+                    display_target(&applied.command)
+                );
+                if let (Some(destination), Some(hash)) = (destination, hash) {
+                    self.index_filed(&destination, hash);
+                }
+            }
+            Ok((applied, _)) => log::error!(
+                "Failed to auto-apply suggestion #{}: {}",
+                applied.id,
+                applied.error.unwrap_or_default()
+            ),
+            Err(e) => log::error!("Failed to auto-apply suggestion #{}: {}", entry.id, e),
+        }
+    }
+
+    /// The suggestion for a file that is not already filed: the first matching rule, or
+    /// the classifiers' placement, or an indeterminate command for the reviewer.
+    async fn place(&self, file: &File) -> Option<Command> {
+        // Files nothing could place are still queued so the reviewer can pick a course.
+        let command = match self.rules.evaluate(file) {
+            Some(command) => command?,
+            None => match classify(&self.classifiers_for(&file.path), file).await {
+                Some(classification) => {
+                    let (directory, rename) = match &classification.placement {
+                        Placement::Course(course) => {
+                            let Some(directory) = self.directory_map.get(&course.name) else {
+                                log::error!("No directory for {}", course.name);
+                                return None;
+                            };
+                            let category = self.categorizer.categorize(course, &file.name);
+                            let context =
+                                NameContext::for_file(&file.path, Some(&course.name), category);
+                            let template = course
+                                .rename_template
+                                .as_deref()
+                                .or(self.rename_template.as_deref());
+                            (
+                                category.map_or(directory.clone(), |c| directory.join(c)),
+                                propose_name(template, &context, &file.name),
+                            )
+                        }
+                        Placement::Directory(directory) => {
+                            let context = NameContext::for_file(&file.path, None, None);
+                            (directory.clone(), propose_name(None, &context, &file.name))
+                        }
+                    };
+                    Command {
+                        file_path: Some(file.path.clone()),
+                        command: CommandEnum::Move,
+                        destination: Some(directory),
+                        reason: Some(classification.reason),
+                        rename,
+                        confidence: Some(classification.confidence),
+                        tags: Vec::new(),
+                    }
+                }
+                None => {
+                    log::info!("Could not classify {}", file.name);
+                    Command {
+                        file_path: Some(file.path.clone()),
+                        command: CommandEnum::Indeterminate,
+                        destination: None,
+                        reason: None,
+                        rename: None,
+                        confidence: None,
+                        tags: Vec::new(),
+                    }
+                }
+            },
+        };
+        Some(command)
+    }
+
+    /// Adds a file that was just filed to the content index, without walking the tree.
+    fn index_filed(&self, path: &Path, hash: String) {
+        if !path.starts_with(&self.base_path) {
+            return;
+        }
+        let mut index = self.content_index.lock().unwrap();
+        if let Err(e) = index.update(&self.data_dir, |index| index.insert(path, hash)) {
+            log::warn!("Could not index {}: {}", path.display(), e);
+        }
+    }
+
+    /// Brings the content index up to date with the organized tree, off the async runtime.
+    /// The walk works on a copy so lookups are not held up meanwhile.
+    async fn refresh_index(&self) {
+        let index = self.content_index.clone();
+        let base_path = self.base_path.clone();
+        // Watched folders under `base_path` are not part of the organized tree.
+        let unindexed: Vec<PathBuf> = self
+            .watch_roots()
+            .into_iter()
+            .map(|root| root.path)
+            .chain([self.data_dir.clone()])
+            .filter(|path| !base_path.starts_with(path))
+            .collect();
+        let data_dir = self.data_dir.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut fresh = {
+                let mut index = index.lock().unwrap();
+                index.reload_if_changed(&data_dir);
+                index.clone()
+            };
+            if fresh.refresh(&base_path, &unindexed)? {
+                fresh.save(&data_dir)?;
+            }
+            *index.lock().unwrap() = fresh;
+            std::io::Result::Ok(())
+        })
+        .await;
         match result {
-            Ok(events) => events
-                .iter()
-                .for_each(|event| action_tx.send(event.clone()).unwrap()),
-            Err(errors) => errors.iter().for_each(|error| log::error!("{error:?}")),
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Could not update the content index: {}", e),
+            Err(e) => log::error!("Indexing {} panicked: {}", self.base_path.display(), e),
         }
     }
 
-    Ok(())
+    async fn content_hash(&self, path: &Path) -> Option<String> {
+        let task_path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || hash_file(&task_path)).await {
+            Ok(Ok(hash)) => Some(hash),
+            Ok(Err(e)) => {
+                log::warn!("Could not hash {}: {}", path.display(), e);
+                None
+            }
+            Err(e) => {
+                log::error!("Hashing {} panicked: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Gives a download a versioned name when a different file with its name is already
+    /// filed in its destination, so it is kept next to the old version instead of
+    /// replacing it.
+    fn version(&self, command: &mut Command, file_name: &str) {
+        if command.command != CommandEnum::Move {
+            return;
+        }
+        let Some(destination) = &command.destination else {
+            return;
+        };
+        let name = command.rename.as_deref().unwrap_or(file_name);
+        // Identical contents were caught as a duplicate, so these differ.
+        if self
+            .content_index
+            .lock()
+            .unwrap()
+            .contains(&destination.join(name))
+        {
+            command.rename = Some(versioned_name(destination, name));
+        }
+    }
+
+    fn retarget(&self, from: &Path, to: &Path) {
+        match self.queue.retarget(from, to) {
+            Ok(entries) => entries.iter().for_each(|entry| {
+                log::info!("Suggestion #{} now follows {}", entry.id, to.display())
+            }),
+            Err(e) => log::error!("Failed to update suggestions for {}: {}", from.display(), e),
+        }
+    }
+
+    fn invalidate(&self, path: &Path) {
+        match self
+            .queue
+            .invalidate(path, "File was removed or moved away before review")
+        {
+            Ok(entries) => entries.iter().for_each(|entry| {
+                log::info!(
+                    "Suggestion #{} invalidated, {} is gone",
+                    entry.id,
+                    path.display()
+                )
+            }),
+            Err(e) => log::error!("Failed to update suggestions for {}: {}", path.display(), e),
+        }
+    }
 }
 
-fn create_debouncer<P: AsRef<Path>>(
-    path: P,
-    tx: Sender<std::result::Result<Vec<DebouncedEvent>, Vec<Error>>>,
-) -> notify::Result<(Debouncer<FsEventWatcher, FileIdMap>, ())> {
-    let mut debouncer = new_debouncer(Duration::from_secs(1), None, tx)?;
-    debouncer
-        .watcher()
-        .watch(path.as_ref(), RecursiveMode::Recursive)?;
-    debouncer
-        .cache()
-        .add_root(path.as_ref(), RecursiveMode::Recursive);
+/// The course-code grep always runs first, then the model learned from past reviews;
+/// the LLM is only consulted when neither places the file.
+/// Fails if a course has an invalid regex pattern.
+fn create_classifiers(
+    config: &Config,
+    directory_map: &HashMap<String, PathBuf>,
+    data_dir: &Path,
+) -> std::result::Result<Vec<Box<dyn Classifier>>, Box<dyn std::error::Error>> {
+    let course_directories = config
+        .courses
+        .iter()
+        .filter_map(|course| Some((directory_map.get(&course.name)?.clone(), course.clone())))
+        .collect();
+    let mut classifiers: Vec<Box<dyn Classifier>> = vec![
+        Box::new(CourseCodeClassifier::new(config.courses.clone())?),
+        Box::new(ModelClassifier::new(
+            data_dir.to_path_buf(),
+            course_directories,
+        )),
+    ];
+    let llm_config = match &config.llm {
+        Some(llm_config) => llm_config.clone(),
+        // Older configs have no llm section; keep using OpenAI if its key is around.
+        None if std::env::var_os(LEGACY_API_KEY_ENV).is_some() => LlmConfig::default(),
+        None => {
+            log::info!("No llm section in config, LLM classification is disabled");
+            return Ok(classifiers);
+        }
+    };
+    if !llm_config.enabled {
+        return Ok(classifiers);
+    }
+    match ChatClient::new(&llm_config) {
+        Ok(client) => {
+            log::info!(
+                "Using {} at {} for LLM classification",
+                llm_config.model,
+                llm_config.base_url
+            );
+            classifiers.push(Box::new(LlmClassifier::new(client, config.courses.clone())));
+        }
+        Err(e) => log::error!("Failed to create LLM client: {}", e),
+    }
+    Ok(classifiers)
+}
 
-    Ok((debouncer, ()))
+fn create_debouncer<W: Watcher, F: DebounceEventHandler>(
+    handler: F,
+) -> notify::Result<Debouncer<W, FileIdMap>> {
+    new_debouncer_opt::<_, W, FileIdMap>(
+        Duration::from_secs(1),
+        None,
+        handler,
+        FileIdMap::new(),
+        notify::Config::default(),
+    )
 }
 
-fn create_directory_map(
+pub fn create_directory_map(
     config: &Config,
 ) -> std::result::Result<HashMap<String, PathBuf>, Box<dyn std::error::Error>> {
     let mut map = HashMap::new();
@@ -134,77 +1086,450 @@ fn create_directory_map(
     Ok(map)
 }
 
-fn parse_event(
-    event: DebouncedEvent,
-) -> std::result::Result<ParseEventResult, Box<dyn std::error::Error>> {
-    if let EventKind::Create(CreateKind::File) | EventKind::Modify(ModifyKind::Any) =
-        event.event.kind
-    {
-        log::info!("Running some code");
-        let path = event
-            .event
-            .paths
-            .get(0)
-            .and_then(|p| p.to_str())
-            .ok_or("Invalid path")?;
+enum FileChange {
+    /// Created or written to; possibly a new or finished download.
+    Written(PathBuf),
+    Renamed(PathBuf, PathBuf),
+    /// Deleted or moved out of the watched folder.
+    Removed(PathBuf),
+}
 
-        let data = get_where_froms_attribute(path)?.ok_or("Attribute not found")?;
+fn file_change(event: &DebouncedEvent) -> Option<FileChange> {
+    let paths = &event.event.paths;
+    // FSEvents reports downloads as Create(File)/Modify(Any) and renames as a Name(Any) per
+    // path. inotify reports writes as Modify(Data(..)) and the browser's final rename from
+    // its partial file as Modify(Name(Both)), whose paths are [from, to].
+    match event.event.kind {
+        EventKind::Create(CreateKind::File)
+        | EventKind::Modify(ModifyKind::Any)
+        | EventKind::Modify(ModifyKind::Data(DataChange::Any | DataChange::Content))
+        | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            paths.last().cloned().map(FileChange::Written)
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match paths.as_slice() {
+            [from, to] => Some(FileChange::Renamed(from.clone(), to.clone())),
+            _ => paths.last().cloned().map(FileChange::Written),
+        },
+        EventKind::Modify(ModifyKind::Name(RenameMode::Any)) => {
+            let path = paths.last()?.clone();
+            Some(if path.exists() {
+                FileChange::Written(path)
+            } else {
+                FileChange::Removed(path)
+            })
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+            paths.last().cloned().map(FileChange::Removed)
+        }
+        _ => None,
+    }
+}
 
-        let reader = io::Cursor::new(data);
-        let plist_value = plist::Value::from_reader(reader)
-            .map_err(|e| format!("Failed to parse plist: {}", e))?;
+/// Extracts on the blocking pool so a large or malformed document cannot stall the event loop.
+async fn read_text(path: &Path, config: &ExtractionConfig) -> Option<String> {
+    let path = path.to_path_buf();
+    let config = config.clone();
+    let task_path = path.clone();
+    match tokio::task::spawn_blocking(move || extract_text(&task_path, &config)).await {
+        Ok(Ok(text)) => text,
+        Ok(Err(e)) => {
+            log::warn!("Could not read text from {}: {}", path.display(), e);
+            None
+        }
+        Err(e) => {
+            log::error!("Text extraction for {} panicked: {}", path.display(), e);
+            None
+        }
+    }
+}
 
-        if let Value::Array(array) = plist_value {
-            for item in array {
-                if let Value::String(url) = item {
-                    let file_name = extract_filename(path).ok_or("Failed to extract filename")?;
-                    return Ok(ParseEventResult::File(File {
-                        name: file_name.to_string(),
-                        url,
-                        path: path.into(),
-                    }));
-                }
+fn extract_filename(file_path: &str) -> Option<&str> {
+    file_path.rsplit('/').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use notify::Event;
+    use std::fs;
+
+    fn pipeline(dir: &Path) -> Arc<Pipeline> {
+        fs::create_dir_all(dir.join("Downloads")).unwrap();
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+download_path: {dir}/Downloads
+base_path: {dir}/School
+current_term: 2A
+start_year: 2024
+end_year: 2029
+coop: true
+courses:
+- name: CS246
+  description: Object-Oriented Software Development
+api_key: ''
+readiness:
+  settle_secs: 0
+  poll_millis: 10
+",
+            dir = dir.display()
+        ))
+        .unwrap();
+        Arc::new(Pipeline::open(&config, &dir.join("data")).unwrap())
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> DebouncedEvent {
+        paths
+            .iter()
+            .fold(Event::new(kind), |event, path| {
+                event.add_path(path.to_path_buf())
+            })
+            .into()
+    }
+
+    /// Waits for every file the pipeline is watching to be processed.
+    async fn settled(pipeline: &Pipeline) {
+        for _ in 0..500 {
+            if pipeline.waiting.lock().unwrap().is_empty() {
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        panic!("files never settled");
     }
 
-    Ok(ParseEventResult::Empty)
-}
+    #[tokio::test]
+    async fn new_download_gets_a_suggestion() {
+        let dir = temp_dir("monitor-download");
+        let pipeline = pipeline(&dir);
+        let path = dir.join("Downloads").join("CS246_A1.pdf");
+        fs::write(&path, "assignment").unwrap();
 
-fn craft_staring_prompt(courses: Vec<Course>) -> String {
-    let prompt = format!("
-    Your job is to determine whether a downloaded folder is a file that corresponds to one of the following courses: {:#?}
-    \n
-    If so, return the course name. If not, return the string NONE.\n
-    You will be given the URL of where the file was downloaded from and the name of the file.
-    If the URL contains 'learn.uwaterloo.ca', it is highly likely but not guaranteed that the file is a course file.
-    You should check to see if the file name or the URL contains the course code or the course name.", courses);
-    return prompt.to_string();
-}
+        pipeline.handle(&event(EventKind::Create(CreateKind::File), &[&path]));
+        settled(&pipeline).await;
 
-fn get_file_contents(file_path: &str) -> std::result::Result<Vec<u8>, std::io::Error> {
-    std::fs::read(file_path)
-}
+        let entries = pipeline.queue.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.status, Status::Pending);
+        assert_eq!(entry.command.command, CommandEnum::Move);
+        assert_eq!(entry.command.file_path.as_deref(), Some(path.as_path()));
+        assert_eq!(
+            entry.command.destination,
+            Some(dir.join("School").join("2A").join("CS246"))
+        );
+        assert!(matches!(entry.command.reason, Some(ReasonEnum::CourseCode)));
+    }
 
-fn grep_course_code(
-    file: &File,
-    courses: Vec<Course>,
-) -> std::result::Result<GrepCourseCodeResult, Box<dyn std::error::Error>> {
-    let sanitized_name: String = file.name.chars().filter(|c| !c.is_whitespace()).collect();
-    for course in courses {
-        if sanitized_name.contains(&course.name) || file.url.contains(&course.name) {
-            return Ok(GrepCourseCodeResult::Course(course));
+    #[tokio::test(flavor = "multi_thread")]
+    async fn watcher_reports_a_new_download() {
+        let dir = temp_dir("monitor-watcher");
+        let pipeline = pipeline(&dir);
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut debouncer =
+            create_debouncer::<RecommendedWatcher, _>(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    let _ = events_tx.send(event);
+                }
+            })
+            .unwrap();
+        for root in pipeline.watch_roots() {
+            assert!(watch_root(&mut debouncer, &root));
         }
+        let path = dir.join("Downloads").join("CS246_A1.pdf");
+        fs::write(&path, "assignment").unwrap();
+
+        let suggested = tokio::time::timeout(Duration::from_secs(20), async {
+            loop {
+                let event = events_rx.recv().await.unwrap();
+                pipeline.handle(&event);
+                settled(&pipeline).await;
+                let entries = pipeline.queue.entries().unwrap();
+                if !entries.is_empty() {
+                    return entries;
+                }
+            }
+        })
+        .await
+        .expect("no suggestion for the new download");
+        assert_eq!(suggested.len(), 1);
+        assert_eq!(
+            suggested[0].command.file_path.as_deref(),
+            Some(path.as_path())
+        );
+        assert_eq!(suggested[0].command.command, CommandEnum::Move);
     }
-    Ok(GrepCourseCodeResult::Empty)
-}
 
-fn get_where_froms_attribute(
-    file_path: &str,
-) -> std::result::Result<std::option::Option<Vec<u8>>, std::io::Error> {
-    xattr::get(file_path, "com.apple.metadata:kMDItemWhereFroms")
-}
+    #[tokio::test]
+    async fn partial_download_is_suggested_once_renamed() {
+        let dir = temp_dir("monitor-partial");
+        let pipeline = pipeline(&dir);
+        let partial = dir.join("Downloads").join("CS246_A1.pdf.crdownload");
+        let path = dir.join("Downloads").join("CS246_A1.pdf");
+        fs::write(&partial, "assignment").unwrap();
 
-fn extract_filename(file_path: &str) -> Option<&str> {
-    file_path.rsplit('/').next()
+        pipeline.handle(&event(EventKind::Create(CreateKind::File), &[&partial]));
+        settled(&pipeline).await;
+        assert!(pipeline.queue.entries().unwrap().is_empty());
+
+        fs::rename(&partial, &path).unwrap();
+        pipeline.handle(&event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&partial, &path],
+        ));
+        settled(&pipeline).await;
+        let entries = pipeline.queue.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].command.file_path.as_deref(),
+            Some(path.as_path())
+        );
+    }
+
+    fn daemon(dir: &Path) -> (Arc<Daemon>, std::sync::mpsc::Receiver<Message>) {
+        let (messages, received) = channel();
+        let daemon = Daemon {
+            pipeline: RwLock::new(pipeline(dir)),
+            state: Arc::new(RunState::default()),
+            started_at: Local::now(),
+            messages,
+        };
+        (Arc::new(daemon), received)
+    }
+
+    #[tokio::test]
+    async fn control_requests_round_trip_over_the_socket() {
+        let dir = temp_dir("monitor-control");
+        let (daemon, received) = daemon(&dir);
+        let data_dir = dir.join("data");
+        let lock = DaemonLock::acquire(&data_dir).unwrap();
+        tokio::spawn(serve(
+            UnixListener::bind(lock.socket_path()).unwrap(),
+            daemon.clone(),
+        ));
+        // The watcher thread answers reloads; this one turns them all down.
+        std::thread::spawn(move || {
+            for message in received {
+                match message {
+                    Message::Reload(reply) => {
+                        let _ = reply.send(Err("bad config".to_string()));
+                    }
+                    Message::Stop => return,
+                    Message::Events(_) => {}
+                }
+            }
+        });
+        let send = |request| {
+            let data_dir = data_dir.clone();
+            tokio::task::spawn_blocking(move || crate::control::send(&data_dir, request).unwrap())
+        };
+
+        let status = send(Request::Status).await.unwrap().status.unwrap();
+        assert_eq!(status.pid, std::process::id());
+        assert!(!status.paused);
+        assert_eq!(status.roots, vec![dir.join("Downloads")]);
+        assert_eq!(status.pending, 0);
+
+        let response = send(Request::Pause).await.unwrap();
+        assert!(response.ok);
+        assert!(daemon.state.paused.load(Ordering::SeqCst));
+        assert!(send(Request::Status).await.unwrap().status.unwrap().paused);
+
+        let response = send(Request::Resume).await.unwrap();
+        assert_eq!(response.message.as_deref(), Some("Resumed"));
+        assert!(!daemon.state.paused.load(Ordering::SeqCst));
+
+        let response = send(Request::Reload).await.unwrap();
+        assert!(!response.ok);
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Kept the current config: bad config")
+        );
+
+        let response = send(Request::Stop).await.unwrap();
+        assert!(response.ok);
+        let response = send(Request::Reload).await.unwrap();
+        assert_eq!(
+            response.message.as_deref(),
+            Some("The monitor is shutting down")
+        );
+    }
+
+    #[test]
+    fn signals_stop_once_then_exit_and_hangup_reloads() {
+        let (messages, received) = channel();
+        let mut stopping = false;
+
+        assert_eq!(Received::Hangup.handle(&mut stopping, &messages), None);
+        assert!(matches!(received.try_recv(), Ok(Message::Reload(_))));
+        assert!(!stopping);
+
+        assert_eq!(Received::Terminate.handle(&mut stopping, &messages), None);
+        assert!(matches!(received.try_recv(), Ok(Message::Stop)));
+        assert!(stopping);
+
+        assert_eq!(Received::Hangup.handle(&mut stopping, &messages), None);
+        assert!(matches!(received.try_recv(), Ok(Message::Reload(_))));
+        assert_eq!(
+            Received::Interrupt.handle(&mut stopping, &messages),
+            Some(130)
+        );
+        assert_eq!(
+            Received::Terminate.handle(&mut stopping, &messages),
+            Some(143)
+        );
+        assert!(received.try_recv().is_err());
+
+        let mut stopping = false;
+        assert_eq!(Received::Interrupt.handle(&mut stopping, &messages), None);
+        assert!(matches!(received.try_recv(), Ok(Message::Stop)));
+    }
+
+    /// Stops `pipeline` the way the watcher thread does on `Message::Stop`, with `received`
+    /// still waiting in its channel, and returns what `drain` returned.
+    async fn shut_down(
+        pipeline: &Arc<Pipeline>,
+        received: Vec<Message>,
+        timeout: Duration,
+    ) -> usize {
+        let (action_tx, mut action_rx) = tokio::sync::mpsc::unbounded_channel::<DebouncedEvent>();
+        let events = pipeline.clone();
+        let event_task = tokio::spawn(async move {
+            while let Some(event) = action_rx.recv().await {
+                events.handle(&event);
+            }
+        });
+        pipeline.state.stopping.store(true, Ordering::SeqCst);
+        let state = pipeline.state.clone();
+        tokio::task::spawn_blocking(move || {
+            drain(
+                &state,
+                received.into_iter(),
+                Path::new("/config.yaml"),
+                action_tx,
+                &event_task,
+                timeout,
+            )
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_waits_for_files_being_processed() {
+        let dir = temp_dir("monitor-drain");
+        let pipeline = pipeline(&dir);
+        let state = pipeline.state.clone();
+        let (started_tx, started_rx) = channel();
+        let in_flight = std::thread::spawn(move || {
+            let _processing = Processing::start(&state.processing);
+            started_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+        });
+        started_rx.recv().unwrap();
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            shut_down(&pipeline, Vec::new(), Duration::from_secs(10)).await,
+            0
+        );
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        in_flight.join().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_gives_up_after_the_timeout() {
+        let dir = temp_dir("monitor-drain-timeout");
+        let pipeline = pipeline(&dir);
+        let _processing = Processing::start(&pipeline.state.processing);
+
+        assert_eq!(
+            shut_down(&pipeline, Vec::new(), Duration::from_millis(100)).await,
+            1
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn events_received_before_stopping_are_left_for_the_next_start() {
+        let dir = temp_dir("monitor-drain-events");
+        let pipeline = pipeline(&dir);
+        let path = dir.join("Downloads").join("CS246_A1.pdf");
+        fs::write(&path, "assignment").unwrap();
+        let received = vec![Message::Events(Ok(vec![event(
+            EventKind::Create(CreateKind::File),
+            &[&path],
+        )]))];
+
+        assert_eq!(
+            shut_down(&pipeline, received, Duration::from_secs(10)).await,
+            0
+        );
+        settled(&pipeline).await;
+        assert!(pipeline.queue.entries().unwrap().is_empty());
+        assert!(path.exists());
+    }
+
+    /// Files `contents` as `name` in the CS246 folder and indexes the organized tree.
+    async fn filed(pipeline: &Pipeline, dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let course = dir.join("School").join("2A").join("CS246");
+        fs::create_dir_all(&course).unwrap();
+        let path = course.join(name);
+        fs::write(&path, contents).unwrap();
+        pipeline.refresh_index().await;
+        path
+    }
+
+    /// Downloads `contents` as `name` and returns the suggestion for it.
+    async fn suggestion(
+        pipeline: &Arc<Pipeline>,
+        dir: &Path,
+        name: &str,
+        contents: &str,
+    ) -> Command {
+        let path = dir.join("Downloads").join(name);
+        fs::write(&path, contents).unwrap();
+        pipeline.handle(&event(EventKind::Create(CreateKind::File), &[&path]));
+        settled(pipeline).await;
+        let entries = pipeline.queue.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        entries[0].command.clone()
+    }
+
+    #[tokio::test]
+    async fn identical_download_is_a_duplicate() {
+        let dir = temp_dir("monitor-duplicate");
+        let pipeline = pipeline(&dir);
+        let existing = filed(&pipeline, &dir, "CS246_A1.pdf", "assignment").await;
+
+        let command = suggestion(&pipeline, &dir, "CS246_A1 (1).pdf", "assignment").await;
+        assert_eq!(command.command, CommandEnum::Duplicate);
+        assert_eq!(command.destination, Some(existing));
+        assert!(matches!(command.reason, Some(ReasonEnum::Duplicate)));
+    }
+
+    #[tokio::test]
+    async fn changed_download_with_a_filed_name_is_versioned() {
+        let dir = temp_dir("monitor-versioned");
+        let pipeline = pipeline(&dir);
+        filed(&pipeline, &dir, "CS246_A1.pdf", "assignment").await;
+
+        let command = suggestion(&pipeline, &dir, "CS246_A1.pdf", "assignment, corrected").await;
+        assert_eq!(command.command, CommandEnum::Move);
+        assert_eq!(
+            command.destination,
+            Some(dir.join("School").join("2A").join("CS246"))
+        );
+        assert_eq!(command.rename.as_deref(), Some("CS246_A1 (v2).pdf"));
+    }
+
+    #[tokio::test]
+    async fn empty_download_is_never_a_duplicate() {
+        let dir = temp_dir("monitor-empty");
+        let pipeline = pipeline(&dir);
+        filed(&pipeline, &dir, "CS246_notes.txt", "").await;
+
+        let command = suggestion(&pipeline, &dir, "CS246_A1.txt", "").await;
+        assert_eq!(command.command, CommandEnum::Move);
+        assert!(matches!(command.reason, Some(ReasonEnum::CourseCode)));
+    }
 }
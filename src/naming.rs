@@ -0,0 +1,354 @@
+use crate::matcher::find_mentions;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::path::Path;
+
+/// The values a rename template can use.
+#[derive(Debug, Clone)]
+pub struct NameContext {
+    pub course: Option<String>,
+    pub category: Option<String>,
+    /// The file name without its extension or browser ` (1)` or `-2` suffix.
+    pub stem: String,
+    pub ext: String,
+    /// The first number in the name that is not part of a course code, like the 3 in
+    /// `Assignment 3.pdf` or the 5 in `lec_05.pdf`.
+    pub index: Option<u32>,
+    /// When the file was downloaded, taken from its modification time.
+    pub date: DateTime<Local>,
+}
+
+impl NameContext {
+    pub fn for_file(path: &Path, course: Option<&str>, category: Option<&str>) -> NameContext {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let stem = strip_safari_suffix(path, strip_browser_suffix(&stem)).to_string();
+        let date = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now());
+        NameContext {
+            course: course.map(str::to_string),
+            category: category.map(str::to_string),
+            index: detect_index(&stem),
+            ext: path
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            stem,
+            date,
+        }
+    }
+
+    /// The file's current name with any browser suffix removed.
+    fn clean_name(&self) -> String {
+        if self.ext.is_empty() {
+            self.stem.clone()
+        } else {
+            format!("{}.{}", self.stem, self.ext)
+        }
+    }
+}
+
+/// The name a file should get: the rendered template if there is one, otherwise its own
+/// name without the ` (1)` a browser adds to repeat downloads. `None` if nothing changes.
+pub fn propose_name(
+    template: Option<&str>,
+    context: &NameContext,
+    current: &str,
+) -> Option<String> {
+    let name = match template {
+        Some(template) => match render(template, context) {
+            Ok(name) => name,
+            Err(e) => {
+                log::info!("Not renaming {}: {}", current, e);
+                context.clean_name()
+            }
+        },
+        None => context.clean_name(),
+    };
+    Some(name).filter(|name| !name.is_empty() && name != current)
+}
+
+/// Fills in a template like `{course}_{category}_{index:02}_{date:%Y%m%d}.{ext}`.
+/// `{{` and `}}` are literal braces. Fails on unknown variables and on variables the
+/// file has no value for.
+pub fn render(template: &str, context: &NameContext) -> Result<String, String> {
+    let mut name = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                name.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                name.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(format!("Unclosed {{ in {}", template)),
+                    }
+                }
+                let (variable, spec) = match placeholder.split_once(':') {
+                    Some((variable, spec)) => (variable, Some(spec)),
+                    None => (placeholder.as_str(), None),
+                };
+                name.push_str(&expand(variable.trim(), spec, context)?);
+            }
+            '}' => return Err(format!("Unmatched }} in {}", template)),
+            c => name.push(c),
+        }
+    }
+    // A template must not be able to move the file somewhere else.
+    let name = name.replace(['/', '\\'], "-");
+    Ok(name.trim().trim_end_matches('.').to_string())
+}
+
+fn expand(variable: &str, spec: Option<&str>, context: &NameContext) -> Result<String, String> {
+    let missing = || format!("no {} for this file", variable);
+    let text = |value: &Option<String>| -> Result<String, String> {
+        match spec {
+            None => value.clone().ok_or_else(missing),
+            Some(spec) => Err(format!("{{{}}} takes no format, got {}", variable, spec)),
+        }
+    };
+    match variable {
+        "course" => text(&context.course),
+        "category" => text(&context.category),
+        "stem" => text(&Some(context.stem.clone())),
+        "ext" => text(&Some(context.ext.clone())),
+        "index" => {
+            let index = context.index.ok_or_else(missing)?;
+            match spec {
+                None => Ok(index.to_string()),
+                Some(spec) => {
+                    let width: usize = spec
+                        .trim_start_matches('0')
+                        .parse()
+                        .map_err(|_| format!("Invalid index format {}", spec))?;
+                    Ok(format!("{:0width$}", index, width = width))
+                }
+            }
+        }
+        "date" => {
+            let spec = spec.unwrap_or("%Y-%m-%d");
+            // Formatting with an invalid specifier panics, so check it first.
+            if StrftimeItems::new(spec).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("Invalid date format {}", spec));
+            }
+            Ok(context.date.format(spec).to_string())
+        }
+        _ => Err(format!("Unknown variable {{{}}}", variable)),
+    }
+}
+
+/// Removes the ` (1)`, ` (2)`, ... that browsers append to repeated downloads.
+pub fn strip_browser_suffix(stem: &str) -> &str {
+    let Some(open) = stem.rfind(" (") else {
+        return stem;
+    };
+    let inner = &stem[open + 2..];
+    match inner.strip_suffix(')') {
+        Some(number) if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => {
+            &stem[..open]
+        }
+        _ => stem,
+    }
+}
+
+/// Removes the `-2`, `-3`, ... Safari appends to a repeated download. Names like
+/// `lab-2.pdf` end the same way, so this only happens while the name without the suffix
+/// is taken beside `path`, which is when Safari adds one.
+fn strip_safari_suffix<'a>(path: &Path, stem: &'a str) -> &'a str {
+    let Some((base, number)) = stem.rsplit_once('-') else {
+        return stem;
+    };
+    let repeat = !base.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+        && number.parse::<u32>().is_ok_and(|number| number >= 2);
+    let original = match path.extension() {
+        Some(extension) => format!("{}.{}", base, extension.to_string_lossy()),
+        None => base.to_string(),
+    };
+    if repeat && path.with_file_name(original).exists() {
+        base
+    } else {
+        stem
+    }
+}
+
+fn detect_index(stem: &str) -> Option<u32> {
+    // Catalog numbers have three or more digits; `lec_05` is a lecture, not a course.
+    let course_numbers: Vec<String> = find_mentions(stem)
+        .into_iter()
+        .map(|mention| mention.number)
+        .filter(|number| number.len() >= 3)
+        .collect();
+    stem.split(|c: char| !c.is_ascii_digit())
+        .filter(|digits| !digits.is_empty())
+        .find(|digits| !course_numbers.iter().any(|number| number == digits))
+        .and_then(|digits| digits.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use chrono::TimeZone;
+
+    fn context() -> NameContext {
+        NameContext {
+            course: Some("CS246".to_string()),
+            category: Some("Assignments".to_string()),
+            stem: "CS246 Assignment 3".to_string(),
+            ext: "pdf".to_string(),
+            index: Some(3),
+            date: Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn renders_template_variables() {
+        let cases = [
+            (
+                "{course}_{category}_{index:02}_{date:%Y%m%d}.{ext}",
+                "CS246_Assignments_03_20240131.pdf",
+            ),
+            ("{stem}.{ext}", "CS246 Assignment 3.pdf"),
+            ("{ course } A{index}.{ext}", "CS246 A3.pdf"),
+            ("{index:3}", "003"),
+            ("{date}", "2024-01-31"),
+            ("{{course}} {course}", "{course} CS246"),
+            ("{course}/../{stem}", "CS246-..-CS246 Assignment 3"),
+            ("  {course}. ", "CS246"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(
+                render(template, &context()).as_deref(),
+                Ok(expected),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_and_unusable_placeholders() {
+        let mut no_course = context();
+        no_course.course = None;
+        let cases = [
+            ("{author}.{ext}", &context(), "Unknown variable {author}"),
+            ("{Course}", &context(), "Unknown variable {Course}"),
+            ("{course", &context(), "Unclosed { in {course"),
+            ("course}", &context(), "Unmatched } in course}"),
+            ("{stem:02}", &context(), "{stem} takes no format, got 02"),
+            ("{index:two}", &context(), "Invalid index format two"),
+            ("{date:%Q}", &context(), "Invalid date format %Q"),
+            ("{course}.{ext}", &no_course, "no course for this file"),
+        ];
+        for (template, context, expected) in cases {
+            assert_eq!(
+                render(template, context),
+                Err(expected.to_string()),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn proposes_a_name_only_when_it_changes() {
+        let cases = [
+            (
+                Some("{course}_A{index}.{ext}"),
+                "CS246 Assignment 3.pdf",
+                Some("CS246_A3.pdf"),
+            ),
+            (Some("{stem}.{ext}"), "CS246 Assignment 3.pdf", None),
+            // A template that cannot be filled in still drops the browser suffix.
+            (
+                Some("{author}.{ext}"),
+                "CS246 Assignment 3 (1).pdf",
+                Some("CS246 Assignment 3.pdf"),
+            ),
+            (
+                None,
+                "CS246 Assignment 3 (1).pdf",
+                Some("CS246 Assignment 3.pdf"),
+            ),
+            (None, "CS246 Assignment 3.pdf", None),
+        ];
+        for (template, current, expected) in cases {
+            assert_eq!(
+                propose_name(template, &context(), current).as_deref(),
+                expected,
+                "{:?} {}",
+                template,
+                current
+            );
+        }
+    }
+
+    #[test]
+    fn strips_browser_suffixes() {
+        let cases = [
+            ("name (1)", "name"),
+            ("name (12)", "name"),
+            ("Assignment 3 (1)", "Assignment 3"),
+            ("name (1) (2)", "name (1)"),
+            ("name", "name"),
+            ("name ()", "name ()"),
+            ("name (v2)", "name (v2)"),
+            ("name(1)", "name(1)"),
+            ("(1)", "(1)"),
+        ];
+        for (stem, expected) in cases {
+            assert_eq!(strip_browser_suffix(stem), expected, "{}", stem);
+        }
+    }
+
+    #[test]
+    fn strips_safari_suffix_only_next_to_the_original() {
+        let dir = temp_dir("naming-safari");
+        std::fs::write(dir.join("name.pdf"), "first").unwrap();
+        std::fs::write(dir.join("README"), "first").unwrap();
+        let cases = [
+            ("name-2.pdf", "name"),
+            ("name-13.pdf", "name"),
+            ("README-2", "README"),
+            // Nothing called lab.pdf is there, so this is a name of its own.
+            ("lab-2.pdf", "lab-2"),
+            ("name-1.pdf", "name-1"),
+            ("name-x.pdf", "name-x"),
+            ("name-+2.pdf", "name-+2"),
+            ("-2.pdf", "-2"),
+        ];
+        for (file_name, stem) in cases {
+            let context = NameContext::for_file(&dir.join(file_name), None, None);
+            assert_eq!(context.stem, stem, "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn detects_the_index() {
+        let cases = [
+            ("CS246 Assignment 3", Some(3)),
+            ("lec_05_final", Some(5)),
+            ("CS246_A1", Some(1)),
+            ("cs 246 tut 7", Some(7)),
+            ("MATH239", None),
+            ("syllabus", None),
+        ];
+        for (stem, expected) in cases {
+            assert_eq!(detect_index(stem), expected, "{}", stem);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use plist::Value;
+use std::io;
+use std::path::Path;
+
+/// Where a downloaded file came from, as recorded by the browser that saved it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Origin {
+    pub url: Option<String>,
+    pub referrer: Option<String>,
+}
+
+/// A source of download origin metadata for a file on disk.
+/// Returns `Ok(None)` when the file carries no metadata this provider understands.
+pub trait OriginProvider: Send + Sync {
+    fn origin(&self, path: &Path) -> io::Result<Option<Origin>>;
+}
+
+/// macOS `com.apple.metadata:kMDItemWhereFroms`: a binary plist array of `[url, referrer]`.
+pub struct WhereFroms;
+
+/// freedesktop `user.xdg.origin.url` / `user.xdg.referrer.url`, written by Chromium and Firefox on Linux.
+pub struct XdgOrigin;
+
+const WHERE_FROMS_ATTRIBUTE: &str = "com.apple.metadata:kMDItemWhereFroms";
+const XDG_ORIGIN_ATTRIBUTE: &str = "user.xdg.origin.url";
+const XDG_REFERRER_ATTRIBUTE: &str = "user.xdg.referrer.url";
+
+impl OriginProvider for WhereFroms {
+    fn origin(&self, path: &Path) -> io::Result<Option<Origin>> {
+        let data = match xattr::get(path, WHERE_FROMS_ATTRIBUTE)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let plist_value = Value::from_reader(io::Cursor::new(data)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse plist: {}", e),
+            )
+        })?;
+
+        let mut urls = match plist_value {
+            Value::Array(array) => array
+                .into_iter()
+                .filter_map(|item| item.into_string())
+                .filter(|url| !url.is_empty()),
+            _ => return Ok(None),
+        };
+        let url = urls.next();
+        let referrer = urls.next();
+        if url.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Origin { url, referrer }))
+    }
+}
+
+impl OriginProvider for XdgOrigin {
+    fn origin(&self, path: &Path) -> io::Result<Option<Origin>> {
+        let url = get_string_attribute(path, XDG_ORIGIN_ATTRIBUTE)?;
+        let referrer = get_string_attribute(path, XDG_REFERRER_ATTRIBUTE)?;
+        if url.is_none() && referrer.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Origin { url, referrer }))
+    }
+}
+
+/// The providers to consult on this platform, most authoritative first.
+pub fn default_providers() -> Vec<Box<dyn OriginProvider>> {
+    let mut providers: Vec<Box<dyn OriginProvider>> = Vec::new();
+    if cfg!(target_os = "macos") {
+        providers.push(Box::new(WhereFroms));
+    }
+    providers.push(Box::new(XdgOrigin));
+    providers
+}
+
+/// Asks each provider in turn and returns the first origin found.
+/// A file with no origin metadata at all yields an empty `Origin` rather than an error,
+/// so it can still be classified by name.
+pub fn read_origin(providers: &[Box<dyn OriginProvider>], path: &Path) -> Origin {
+    for provider in providers {
+        match provider.origin(path) {
+            Ok(Some(origin)) => return origin,
+            Ok(None) => {}
+            Err(e) => log::debug!("Could not read origin of {}: {}", path.display(), e),
+        }
+    }
+    Origin::default()
+}
+
+fn get_string_attribute(path: &Path, name: &str) -> io::Result<Option<String>> {
+    let value = xattr::get(path, name)?
+        .map(|bytes| {
+            String::from_utf8_lossy(&bytes)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .filter(|value| !value.is_empty());
+    Ok(value)
+}
@@ -0,0 +1,299 @@
+use crate::review::{Command, CommandEnum};
+use crate::setup::data_dir;
+use crate::store::{Record, SnapshotLog};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Accepted,
+    Rejected,
+    Failed,
+    Snoozed,
+    /// The file was deleted or moved away before the suggestion was reviewed.
+    Invalidated,
+}
+
+/// Identifies a file across renames: its device and inode number.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub device: u64,
+    pub inode: u64,
+}
+
+impl FileIdentity {
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> Option<FileIdentity> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(FileIdentity {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    /// Without inode numbers, suggestions are matched by path alone.
+    #[cfg(not(unix))]
+    pub fn of(_path: &Path) -> Option<FileIdentity> {
+        None
+    }
+}
+
+/// A suggestion in the review queue. `id` is stable for the lifetime of the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub id: u64,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub status: Status,
+    pub command: Command,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snoozed_until: Option<DateTime<Local>>,
+    /// The file the suggestion is about, so later events for it update this entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<FileIdentity>,
+    /// The reviewer changed the destination or name. Later writes to the file keep them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub edited: bool,
+}
+
+impl QueueEntry {
+    /// Pending, or snoozed with the snooze already over.
+    pub fn is_due(&self) -> bool {
+        match self.status {
+            Status::Pending => true,
+            Status::Snoozed => self.snoozed_until.is_none_or(|until| until <= Local::now()),
+            _ => false,
+        }
+    }
+
+    /// Not yet accepted, rejected or invalidated.
+    pub fn is_open(&self) -> bool {
+        matches!(self.status, Status::Pending | Status::Snoozed)
+    }
+
+    fn is_for(&self, path: &Path, identity: Option<FileIdentity>) -> bool {
+        match (identity, self.identity) {
+            (Some(identity), Some(own)) => identity == own,
+            _ => self.command.file_path.as_deref() == Some(path),
+        }
+    }
+}
+
+/// The review queue shared by the daemon and the reviewer, stored as a `SnapshotLog`
+/// so the daemon can keep appending while `nimbus review` is updating statuses.
+pub struct Queue {
+    log: SnapshotLog<QueueEntry>,
+}
+
+impl Record for QueueEntry {
+    fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Queue {
+    pub fn open(dir: &Path) -> io::Result<Queue> {
+        Ok(Queue {
+            log: SnapshotLog::open(dir, "queue")?,
+        })
+    }
+
+    pub fn open_default() -> io::Result<Queue> {
+        Queue::open(&data_dir()?)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.log.flush()
+    }
+
+    /// Queues a suggestion for a file, or replaces the command of the open suggestion
+    /// already queued for the same file unless the reviewer has edited it. Returns the
+    /// entry and whether it is new.
+    pub fn suggest(
+        &self,
+        command: Command,
+        identity: Option<FileIdentity>,
+    ) -> io::Result<(QueueEntry, bool)> {
+        let path = command.file_path.clone().unwrap_or_default();
+        let now = Local::now();
+        let replacement = command.clone();
+        self.log.upsert(
+            |entry| entry.is_open() && entry.is_for(&path, identity),
+            |entry| {
+                if entry.edited {
+                    entry.command.file_path = replacement.file_path;
+                } else {
+                    entry.command = replacement;
+                }
+                entry.identity = identity.or(entry.identity);
+                entry.updated_at = now;
+            },
+            |id| QueueEntry {
+                id,
+                created_at: now,
+                updated_at: now,
+                status: Status::Pending,
+                command,
+                error: None,
+                snoozed_until: None,
+                identity,
+                edited: false,
+            },
+        )
+    }
+
+    /// Whether a scan should leave the file alone: it has an open or rejected suggestion,
+    /// or an accepted copy, tag, skip or indeterminate one, all of which leave it in place.
+    pub fn was_suggested(&self, path: &Path, identity: Option<FileIdentity>) -> io::Result<bool> {
+        Ok(self.entries()?.iter().any(|entry| {
+            entry.is_for(path, identity)
+                && match entry.status {
+                    Status::Pending | Status::Snoozed | Status::Rejected => true,
+                    Status::Accepted => matches!(
+                        entry.command.command,
+                        CommandEnum::Copy
+                            | CommandEnum::Tag
+                            | CommandEnum::Skip
+                            | CommandEnum::Indeterminate
+                    ),
+                    Status::Failed | Status::Invalidated => false,
+                }
+        }))
+    }
+
+    /// Points open suggestions for `from` at `to` after the file was renamed.
+    pub fn retarget(&self, from: &Path, to: &Path) -> io::Result<Vec<QueueEntry>> {
+        self.log.modify_where(
+            |entry| entry.is_open() && entry.command.file_path.as_deref() == Some(from),
+            |entry| {
+                entry.command.file_path = Some(to.to_path_buf());
+                entry.updated_at = Local::now();
+            },
+        )
+    }
+
+    /// Closes open suggestions for a file that no longer exists at `path`.
+    pub fn invalidate(&self, path: &Path, reason: &str) -> io::Result<Vec<QueueEntry>> {
+        self.log.modify_where(
+            |entry| entry.is_open() && entry.command.file_path.as_deref() == Some(path),
+            |entry| {
+                entry.status = Status::Invalidated;
+                entry.error = Some(reason.to_string());
+                entry.snoozed_until = None;
+                entry.updated_at = Local::now();
+            },
+        )
+    }
+
+    /// All entries, oldest first.
+    pub fn entries(&self) -> io::Result<Vec<QueueEntry>> {
+        self.log.all()
+    }
+
+    /// Entries awaiting review, including snoozed ones whose snooze has ended.
+    pub fn pending(&self) -> io::Result<Vec<QueueEntry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(QueueEntry::is_due)
+            .collect())
+    }
+
+    pub fn get(&self, id: u64) -> io::Result<Option<QueueEntry>> {
+        self.log.get(id)
+    }
+
+    pub fn set_status(
+        &self,
+        id: u64,
+        status: Status,
+        error: Option<String>,
+    ) -> io::Result<QueueEntry> {
+        self.modify(id, |entry| {
+            entry.status = status;
+            entry.error = error;
+            entry.snoozed_until = None;
+        })
+    }
+
+    pub fn snooze(&self, id: u64, until: DateTime<Local>) -> io::Result<QueueEntry> {
+        self.modify(id, |entry| {
+            entry.status = Status::Snoozed;
+            entry.snoozed_until = Some(until);
+        })
+    }
+
+    pub fn modify<F: FnOnce(&mut QueueEntry)>(&self, id: u64, change: F) -> io::Result<QueueEntry> {
+        self.log.modify(id, |entry| {
+            change(entry);
+            entry.updated_at = Local::now();
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::review::ReasonEnum;
+    use crate::testing::temp_dir;
+    use std::path::PathBuf;
+
+    fn queue(name: &str) -> Queue {
+        Queue::open(&temp_dir(&format!("queue-{}", name))).unwrap()
+    }
+
+    fn move_to(destination: &str) -> Command {
+        Command {
+            file_path: Some(PathBuf::from("/dl/L01.pdf")),
+            command: CommandEnum::Move,
+            destination: Some(PathBuf::from(destination)),
+            reason: Some(ReasonEnum::CourseCode),
+            rename: None,
+            confidence: Some(0.9),
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn later_writes_update_the_open_suggestion() {
+        let queue = queue("update");
+        let (first, new) = queue.suggest(move_to("/base/CS246"), None).unwrap();
+        assert!(new);
+        let (second, new) = queue.suggest(move_to("/base/MATH239"), None).unwrap();
+        assert!(!new);
+        assert_eq!(second.id, first.id);
+        assert_eq!(
+            second.command.destination,
+            Some(PathBuf::from("/base/MATH239"))
+        );
+    }
+
+    #[test]
+    fn later_writes_keep_the_reviewers_edits() {
+        let queue = queue("edited");
+        let (entry, _) = queue.suggest(move_to("/base/CS246"), None).unwrap();
+        queue
+            .modify(entry.id, |e| {
+                e.command.destination = Some(PathBuf::from("/base/MATH239"));
+                e.command.rename = Some("Lecture 1.pdf".to_string());
+                e.edited = true;
+            })
+            .unwrap();
+        queue.snooze(entry.id, Local::now()).unwrap();
+        let (entry, new) = queue.suggest(move_to("/base/CS246"), None).unwrap();
+        assert!(!new);
+        assert_eq!(entry.status, Status::Snoozed);
+        assert_eq!(
+            entry.command.destination,
+            Some(PathBuf::from("/base/MATH239"))
+        );
+        assert_eq!(entry.command.rename.as_deref(), Some("Lecture 1.pdf"));
+    }
+}
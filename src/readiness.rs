@@ -0,0 +1,255 @@
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+/// When a file in the downloads folder counts as finished.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Globs on the file name that are never looked at, e.g. dotfiles.
+    pub ignore: Vec<String>,
+    /// Extensions browsers give a download while it is in progress. Such files are
+    /// ignored, and `x.pdf` is not ready while `x.pdf.part` still exists beside it.
+    pub partial_extensions: Vec<String>,
+    /// How long size and modification time must stay unchanged.
+    pub settle_secs: u64,
+    pub poll_millis: u64,
+    /// Give up on files that keep changing for longer than this.
+    pub max_wait_secs: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        ReadinessConfig {
+            ignore: vec![".*".to_string(), "~$*".to_string()],
+            partial_extensions: ["crdownload", "part", "partial", "download", "tmp"]
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            settle_secs: 2,
+            poll_millis: 500,
+            max_wait_secs: 60 * 60,
+        }
+    }
+}
+
+/// Filters out partial and ignored files and waits for the rest to settle.
+pub struct Readiness {
+    ignore: Vec<Pattern>,
+    config: ReadinessConfig,
+}
+
+impl Readiness {
+    pub fn new(config: &ReadinessConfig) -> Result<Readiness, glob::PatternError> {
+        Ok(Readiness {
+            ignore: config
+                .ignore
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<_, _>>()?,
+            config: config.clone(),
+        })
+    }
+
+    /// Whether `path`, or any folder it is in below `root`, is partial or matches an
+    /// ignore glob. This covers files inside Safari's `name.pdf.download` bundle and
+    /// inside hidden folders such as `.git`.
+    pub fn is_ignored(&self, path: &Path, root: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return self.is_ignored_name(path);
+        };
+        let mut components = relative.components().peekable();
+        if components.peek().is_none() {
+            return self.is_ignored_name(path);
+        }
+        components.any(|component| self.is_ignored_name(Path::new(component.as_os_str())))
+    }
+
+    fn is_ignored_name(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+            return true;
+        };
+        self.is_partial(path) || self.ignore.iter().any(|pattern| pattern.matches(&name))
+    }
+
+    fn is_partial(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| {
+            self.config
+                .partial_extensions
+                .iter()
+                .any(|partial| extension.eq_ignore_ascii_case(partial.as_str()))
+        })
+    }
+
+    /// Some browsers create the final name early and write into `<name>.part` beside it.
+    fn has_partial_sibling(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            return false;
+        };
+        self.config.partial_extensions.iter().any(|partial| {
+            path.with_file_name(format!("{}.{}", name, partial))
+                .exists()
+        })
+    }
+
+    /// Whether `path` already looks finished: a regular file nothing has written to for
+    /// `settle_secs`, with no partial file beside it. Used for files found by a scan.
+    pub fn is_settled(&self, path: &Path) -> bool {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false;
+        };
+        let settle = Duration::from_secs(self.config.settle_secs);
+        metadata.is_file()
+            && !self.has_partial_sibling(path)
+            && metadata
+                .modified()
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age >= settle))
+    }
+
+    /// Waits until `path` is a regular file whose size and modification time have not
+    /// changed for `settle_secs`. Returns false if it disappears, is renamed away, or
+    /// never settles.
+    pub async fn wait_until_ready(&self, path: &Path) -> bool {
+        let settle = Duration::from_secs(self.config.settle_secs);
+        let poll = Duration::from_millis(self.config.poll_millis.max(10));
+        let started = Instant::now();
+        let mut last: Option<(u64, Option<SystemTime>)> = None;
+        let mut stable_since = Instant::now();
+        loop {
+            let metadata = match tokio::fs::metadata(path).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                // Gone, renamed or a directory: a later event will report the final name.
+                _ => return false,
+            };
+            let snapshot = (metadata.len(), metadata.modified().ok());
+            if last != Some(snapshot) || self.has_partial_sibling(path) {
+                last = Some(snapshot);
+                stable_since = Instant::now();
+            } else if stable_since.elapsed() >= settle {
+                return true;
+            }
+            if started.elapsed() >= Duration::from_secs(self.config.max_wait_secs) {
+                log::warn!(
+                    "{} is still changing after {}s, ignoring it",
+                    path.display(),
+                    self.config.max_wait_secs
+                );
+                return false;
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::fs;
+    use std::io::Write;
+
+    fn readiness(settle_secs: u64) -> Readiness {
+        Readiness::new(&ReadinessConfig {
+            settle_secs,
+            poll_millis: 10,
+            max_wait_secs: 5,
+            ..ReadinessConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn is_ignored() {
+        let root = Path::new("/home/user/Downloads");
+        let readiness = readiness(0);
+        for (path, expected) in [
+            ("notes.pdf", false),
+            ("notes.PDF", false),
+            (".DS_Store", true),
+            ("~$report.docx", true),
+            ("notes.pdf.crdownload", true),
+            ("notes.pdf.PART", true),
+            ("notes.pdf.download", true),
+            ("notes.pdf.download/notes.pdf", true),
+            (".git/config", true),
+            ("course/notes.pdf", false),
+            ("course/~$notes.docx", true),
+        ] {
+            assert_eq!(
+                readiness.is_ignored(&root.join(path), root),
+                expected,
+                "{}",
+                path
+            );
+        }
+        // Outside the root only the file's own name counts.
+        assert!(!readiness.is_ignored(Path::new("/tmp/.cache/notes.pdf"), root));
+        assert!(readiness.is_ignored(Path::new("/tmp/notes.pdf.tmp"), root));
+    }
+
+    #[test]
+    fn is_settled() {
+        let dir = temp_dir("readiness-settled");
+        let path = dir.join("notes.pdf");
+        fs::write(&path, "notes").unwrap();
+        assert!(readiness(0).is_settled(&path));
+        assert!(!readiness(60).is_settled(&path));
+        assert!(!readiness(0).is_settled(&dir));
+        assert!(!readiness(0).is_settled(&dir.join("missing.pdf")));
+
+        fs::write(dir.join("notes.pdf.part"), "").unwrap();
+        assert!(!readiness(0).is_settled(&path));
+    }
+
+    #[tokio::test]
+    async fn waits_until_writes_stop() {
+        let dir = temp_dir("readiness-writes");
+        let path = dir.join("notes.pdf");
+        fs::write(&path, "").unwrap();
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..5 {
+                    std::thread::sleep(Duration::from_millis(200));
+                    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+                    file.write_all(b"page").unwrap();
+                }
+            })
+        };
+
+        let started = Instant::now();
+        assert!(readiness(1).wait_until_ready(&path).await);
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "page".repeat(5));
+        writer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_partial_sibling_to_go() {
+        let dir = temp_dir("readiness-sibling");
+        let path = dir.join("notes.pdf");
+        let partial = dir.join("notes.pdf.part");
+        fs::write(&path, "").unwrap();
+        fs::write(&partial, "notes").unwrap();
+        let browser = {
+            let (path, partial) = (path.clone(), partial.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(300));
+                fs::rename(partial, path).unwrap();
+            })
+        };
+
+        assert!(readiness(0).wait_until_ready(&path).await);
+        assert!(!partial.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "notes");
+        browser.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_files_that_are_gone() {
+        let dir = temp_dir("readiness-gone");
+        assert!(!readiness(0).wait_until_ready(&dir.join("notes.pdf")).await);
+        assert!(!readiness(0).wait_until_ready(&dir).await);
+    }
+}
@@ -1,21 +1,86 @@
-use promkit::preset::Select;
+use crate::cli::Selection;
+use crate::decisions::{Action, Decision, DecisionLog};
+use crate::duplicates::index_filed;
+use crate::executor::{trash_file, MoveExecutor, MoveOutcome};
+use crate::journal::{AppliedBy, Journal, Operation};
+use crate::model::Features;
+use crate::monitor::create_directory_map;
+use crate::naming::{propose_name, NameContext};
+use crate::origin::{default_providers, read_origin};
+use crate::queue::{Queue, QueueEntry, Status};
+use crate::setup::{data_dir, read_config, Config};
+use crate::subfolders::Categorizer;
+use crate::tags::tag_file;
+use chrono::{Duration, Local};
+use glob::Pattern;
+use promkit::preset::{Readline, Select};
 use serde::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
-use std::io::BufRead;
-use std::io::Error;
-use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ReasonEnum {
     Chatgpt,
     CourseCode,
+    /// A user-defined rule from the config, by name.
+    Rule(String),
+    /// The model trained on past review decisions.
+    Learned,
+    /// A file with the same contents is already in the organized tree.
+    Duplicate,
 }
+impl ReasonEnum {
+    /// The name used for this reason on the command line, e.g. `--reason course-code`
+    /// or `--reason rule:receipts`.
+    pub fn slug(&self) -> String {
+        match self {
+            ReasonEnum::Chatgpt => "chatgpt".to_string(),
+            ReasonEnum::CourseCode => "course-code".to_string(),
+            ReasonEnum::Rule(name) => format!("rule:{}", name),
+            ReasonEnum::Learned => "learned".to_string(),
+            ReasonEnum::Duplicate => "duplicate".to_string(),
+        }
+    }
+
+    /// Checks a `--reason` filter: the slug of a reason, or `rule` for every rule.
+    pub fn parse_filter(filter: &str) -> Result<String, String> {
+        const KINDS: [&str; 5] = ["course-code", "chatgpt", "learned", "duplicate", "rule"];
+        let known = match filter.split_once(':') {
+            Some((kind, name)) => kind.eq_ignore_ascii_case("rule") && !name.is_empty(),
+            None => KINDS.iter().any(|kind| kind.eq_ignore_ascii_case(filter)),
+        };
+        if !known {
+            return Err(format!(
+                "expected one of {} or rule:<name>",
+                KINDS.join(", ")
+            ));
+        }
+        Ok(filter.to_string())
+    }
+
+    /// Whether `--reason <filter>` selects this reason. `rule` selects every rule.
+    pub fn matches(&self, filter: &str) -> bool {
+        let slug = self.slug();
+        slug.eq_ignore_ascii_case(filter)
+            || slug
+                .split(':')
+                .next()
+                .is_some_and(|kind| kind.eq_ignore_ascii_case(filter))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum CommandEnum {
     Move,
     Skip,
     Indeterminate,
+    /// Copy to the destination and leave the original in place.
+    Copy,
+    /// Move to the user's trash.
+    Trash,
+    /// Add the command's tags to the file where it is.
+    Tag,
+    /// Move to the user's trash; an identical copy is already filed at `destination`.
+    Duplicate,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,64 +89,654 @@ pub struct Command {
     pub command: CommandEnum,
     pub destination: Option<PathBuf>,
     pub reason: Option<ReasonEnum>,
+    /// New file name to use at the destination, if it should not keep its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+    /// How sure the classifier was, from 0.0 to 1.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    /// Tags to add to the file, for `Tag` commands.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
-const COMMAND_FILE_PATH: &str = "commands.txt";
+/// Everything an interactive review needs besides the entry itself.
+struct ReviewContext<'a> {
+    queue: &'a Queue,
+    journal: &'a Journal,
+    decisions: &'a DecisionLog,
+    executor: &'a MoveExecutor,
+    config: Option<&'a Config>,
+}
 
-pub fn write_command(command: Command) -> Result<(), Box<dyn std::error::Error>> {
-    let file_path = PathBuf::from(COMMAND_FILE_PATH);
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(file_path)?;
-    let serialized_command = serde_json::to_string(&command)?;
-    writeln!(file, "{}", serialized_command)?;
+const ACCEPT: &str = "Accept";
+const REJECT: &str = "Reject";
+const CHANGE_COURSE: &str = "Move to a different course";
+const CUSTOM_DESTINATION: &str = "Type a destination";
+const RENAME: &str = "Rename file";
+const SNOOZE: &str = "Snooze";
+const LATER: &str = "Decide later";
+
+pub fn read_commands(config_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let queue = Queue::open_default()?;
+    let journal = Journal::open_default()?;
+    let decisions = DecisionLog::open_default()?;
+    let config = match read_config(config_path) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            log::warn!("{}. Course choices will be unavailable", e);
+            None
+        }
+    };
+    let executor = executor_for(config.as_ref());
+    let context = ReviewContext {
+        queue: &queue,
+        journal: &journal,
+        decisions: &decisions,
+        executor: &executor,
+        config: config.as_ref(),
+    };
+    for entry in queue.pending()? {
+        review_entry(&context, entry)?;
+    }
 
     Ok(())
 }
 
-pub fn read_commands() -> Result<(), Box<dyn std::error::Error>> {
-    let file_path = PathBuf::from(COMMAND_FILE_PATH);
-    let file = File::open(file_path)?;
-    let reader = std::io::BufReader::new(file);
-    for lines in reader.lines() {
-        let line = lines?;
-        match serde_json::from_str::<Command>(&line) {
-            Ok(obj) => {
-                let mut confirmation_prompt = Select::new(["Y", "N"])
-                    .title(format!("Do you want to execute this command?: {:?}", obj))
-                    .lines(4)
-                    .prompt()?;
-                let confirmation_result = confirmation_prompt.run()?;
-                if confirmation_result == "Y" {
-                    if obj.command == CommandEnum::Move {
-                        let file_path = obj.file_path.unwrap();
-                        let destination = obj.destination.unwrap();
-                        let file_name = get_file_name(file_path.to_str().unwrap()).unwrap();
-                        let new_file_path = destination.join(file_name);
-                        match std::fs::rename(file_path, new_file_path) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                eprintln!("Error moving file: {:?}", e);
-                            }
-                        }
+/// Prompts for one suggestion until the reviewer accepts, rejects, snoozes or defers it.
+/// Edits to the destination or name are saved to the queue and recorded as decisions.
+fn review_entry(
+    context: &ReviewContext,
+    mut entry: QueueEntry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ReviewContext {
+        queue,
+        journal,
+        decisions,
+        executor,
+        config,
+    } = *context;
+    loop {
+        let mut action_prompt = Select::new([
+            ACCEPT,
+            REJECT,
+            CHANGE_COURSE,
+            CUSTOM_DESTINATION,
+            RENAME,
+            SNOOZE,
+            LATER,
+        ])
+        .title(format!(
+            "What do you want to do with this suggestion?: #{} {}",
+            entry.id,
+            describe_command(&entry.command)
+        ))
+        .lines(7)
+        .prompt()?;
+        match action_prompt.run()?.as_str() {
+            ACCEPT => {
+                if needs_destination(&entry) {
+                    eprintln!("Nothing could place this file. Move it to a course or type a destination first");
+                    continue;
+                }
+                let entry = accept_entry(queue, journal, decisions, executor, config, &entry)?;
+                if let Some(error) = entry.error {
+                    eprintln!("Error moving file: {}", error);
+                }
+                return Ok(());
+            }
+            REJECT => {
+                reject_entry(queue, decisions, &entry)?;
+                return Ok(());
+            }
+            CHANGE_COURSE => {
+                let Some(config) = config else {
+                    eprintln!("No config loaded, so there are no courses to choose from");
+                    continue;
+                };
+                if config.courses.is_empty() {
+                    eprintln!("No courses configured");
+                    continue;
+                }
+                let mut course_prompt =
+                    Select::new(config.courses.iter().map(|course| course.name.clone()))
+                        .title("Which course does this file belong to?")
+                        .lines(8)
+                        .prompt()?;
+                let course = course_prompt.run()?;
+                let directory_map = create_directory_map(config)?;
+                let mut destination = directory_map
+                    .get(&course)
+                    .ok_or(format!("No directory for {}", course))?
+                    .clone();
+                let mut rename = entry.command.rename.clone();
+                let chosen = config.courses.iter().find(|c| c.name == course);
+                if let (Some(chosen), Some(path)) = (chosen, &entry.command.file_path) {
+                    let file_name = file_name_of(&entry.command).unwrap_or_default();
+                    let categorizer = Categorizer::new(config)?;
+                    let category = categorizer.categorize(chosen, &file_name);
+                    if let Some(subfolder) = category {
+                        destination.push(subfolder);
                     }
+                    // The old name may have been made from the old course.
+                    let context = NameContext::for_file(path, Some(&course), category);
+                    let template = chosen
+                        .rename_template
+                        .as_deref()
+                        .or(config.rename_template.as_deref());
+                    rename = propose_name(template, &context, &file_name);
+                }
+                entry = redirect(queue, decisions, &entry, destination, Some(course))?;
+                if entry.command.rename != rename {
+                    entry = queue.modify(entry.id, |e| {
+                        e.command.rename = rename.clone();
+                        e.edited = true;
+                    })?;
                 }
             }
-            Err(e) => {
-                eprintln!("Error deserializing line: {:?}", e);
+            CUSTOM_DESTINATION => {
+                let mut destination_prompt = Readline::default()
+                    .title("Where should this file go?")
+                    .validator(
+                        |text| !text.trim().is_empty(),
+                        |_| "Destination cannot be empty".to_string(),
+                    )
+                    .prompt()?;
+                let destination = PathBuf::from(destination_prompt.run()?.trim());
+                entry = redirect(queue, decisions, &entry, destination, None)?;
             }
+            RENAME => {
+                let mut name_prompt = Readline::default()
+                    .title("What should the file be called?")
+                    .validator(
+                        |text| is_file_name(text.trim()),
+                        |text| format!("Must be a file name. Got {} instead", text),
+                    )
+                    .prompt()?;
+                let new_name = name_prompt.run()?.trim().to_string();
+                let updated = queue.modify(entry.id, |e| {
+                    e.command.rename = Some(new_name.clone());
+                    e.edited = true;
+                })?;
+                let mut decision = decision_for(&entry, Action::Rename);
+                decision.chosen_destination = updated.command.destination.clone();
+                decision.new_name = Some(new_name);
+                decisions.record(&decision)?;
+                entry = updated;
+            }
+            SNOOZE => {
+                let mut snooze_prompt = Select::new(["1 hour", "1 day", "1 week"])
+                    .title("Snooze for how long?")
+                    .lines(3)
+                    .prompt()?;
+                let duration = match snooze_prompt.run()?.as_str() {
+                    "1 hour" => Duration::hours(1),
+                    "1 day" => Duration::days(1),
+                    _ => Duration::weeks(1),
+                };
+                queue.snooze(entry.id, Local::now() + duration)?;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Points a suggestion at a new destination, turning it into a move if it was not one.
+fn redirect(
+    queue: &Queue,
+    decisions: &DecisionLog,
+    entry: &QueueEntry,
+    destination: PathBuf,
+    course: Option<String>,
+) -> Result<QueueEntry, Box<dyn std::error::Error>> {
+    let updated = queue.modify(entry.id, |e| {
+        e.command.command = CommandEnum::Move;
+        e.command.destination = Some(destination.clone());
+        e.edited = true;
+    })?;
+    let action = if course.is_some() {
+        Action::ChangeCourse
+    } else {
+        Action::CustomDestination
+    };
+    let mut decision = decision_for(entry, action);
+    decision.chosen_destination = Some(destination);
+    decision.course = course;
+    decisions.record(&decision)?;
+    Ok(updated)
+}
+
+/// A decision about `entry` that keeps its suggested destination, with the file's
+/// features for the learned model. Must be built before the file is moved.
+fn decision_for(entry: &QueueEntry, action: Action) -> Decision {
+    let file_name = file_name_of(&entry.command);
+    let url = entry.command.file_path.as_deref().and_then(|path| {
+        let origin = read_origin(&default_providers(), path);
+        origin.url.or(origin.referrer)
+    });
+    // A duplicate's destination is the filed copy, not a folder the model should learn.
+    let destination = match entry.command.command {
+        CommandEnum::Duplicate => None,
+        _ => entry.command.destination.clone(),
+    };
+    Decision {
+        queue_id: entry.id,
+        decided_at: Local::now(),
+        action,
+        features: file_name
+            .as_deref()
+            .map(|name| Features::extract(name, url.as_deref())),
+        file_name,
+        source_dir: entry
+            .command
+            .file_path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf),
+        suggested_destination: destination.clone(),
+        chosen_destination: destination,
+        course: None,
+        new_name: None,
+    }
+}
+
+/// Rejects a pending suggestion and records the decision.
+fn reject_entry(
+    queue: &Queue,
+    decisions: &DecisionLog,
+    entry: &QueueEntry,
+) -> Result<QueueEntry, Box<dyn std::error::Error>> {
+    let mut decision = decision_for(entry, Action::Reject);
+    decision.chosen_destination = None;
+    let updated = queue.set_status(entry.id, Status::Rejected, None)?;
+    decisions.record(&decision)?;
+    Ok(updated)
+}
+
+fn describe_command(command: &Command) -> String {
+    let mut description = format!("{:?} {}", command.command, display_path(&command.file_path));
+    if let Some(destination) = &command.destination {
+        description.push_str(&format!(" -> {}", destination.display()));
+    }
+    if let Some(rename) = &command.rename {
+        description.push_str(&format!(" as {}", rename));
+    }
+    if !command.tags.is_empty() {
+        description.push_str(&format!(" [{}]", command.tags.join(", ")));
+    }
+    match (&command.reason, command.confidence) {
+        (Some(reason), Some(confidence)) => {
+            description.push_str(&format!(" ({}, {:.0}%)", reason.slug(), confidence * 100.0))
         }
+        (Some(reason), None) => description.push_str(&format!(" ({})", reason.slug())),
+        _ => {}
     }
-    clear_file(COMMAND_FILE_PATH)?;
+    description
+}
 
+fn file_name_of(command: &Command) -> Option<String> {
+    command
+        .file_path
+        .as_ref()
+        .and_then(|p| p.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+pub fn list_commands(json: bool, all: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let queue = Queue::open_default()?;
+    let entries = if all {
+        queue.entries()?
+    } else {
+        queue.pending()?
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    println!(
+        "{:>5}  {:<11}  {:<16}  {:<16}  {:<40}  DESTINATION",
+        "ID", "STATUS", "REASON", "CREATED", "FILE"
+    );
+    for entry in entries {
+        println!(
+            "{:>5}  {:<11}  {:<16}  {:<16}  {:<40}  {}",
+            entry.id,
+            format!("{:?}", entry.status).to_lowercase(),
+            entry
+                .command
+                .reason
+                .as_ref()
+                .map_or("-".to_string(), |r| r.slug()),
+            entry.created_at.format("%Y-%m-%d %H:%M"),
+            display_path(&entry.command.file_path),
+            display_destination(&entry.command),
+        );
+    }
     Ok(())
 }
 
+pub fn accept_commands(
+    config_path: &Path,
+    selection: &Selection,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let queue = Queue::open_default()?;
+    let journal = Journal::open_default()?;
+    let decisions = DecisionLog::open_default()?;
+    let config = read_config(config_path)?;
+    let executor = executor_for(Some(&config));
+    let mut failures = 0;
+    for entry in select_pending(&queue, selection)? {
+        if needs_destination(&entry) {
+            eprintln!(
+                "Skipped #{}: {} has no destination, pick one in `nimbus review`",
+                entry.id,
+                display_path(&entry.command.file_path)
+            );
+            continue;
+        }
+        let entry = accept_entry(
+            &queue,
+            &journal,
+            &decisions,
+            &executor,
+            Some(&config),
+            &entry,
+        )?;
+        match entry.error {
+            None => println!(
+                "Accepted #{}: {}",
+                entry.id,
+                display_path(&entry.command.file_path)
+            ),
+            Some(error) => {
+                failures += 1;
+                eprintln!("Failed #{}: {}", entry.id, error);
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} suggestion(s) could not be applied", failures).into());
+    }
+    Ok(())
+}
+
+pub fn reject_commands(selection: &Selection) -> Result<(), Box<dyn std::error::Error>> {
+    let queue = Queue::open_default()?;
+    let decisions = DecisionLog::open_default()?;
+    for entry in select_pending(&queue, selection)? {
+        reject_entry(&queue, &decisions, &entry)?;
+        println!(
+            "Rejected #{}: {}",
+            entry.id,
+            display_path(&entry.command.file_path)
+        );
+    }
+    Ok(())
+}
+
+/// Whether the entry still needs the reviewer to choose where the file goes.
+fn needs_destination(entry: &QueueEntry) -> bool {
+    entry.command.command == CommandEnum::Indeterminate
+}
+
+/// Executes an entry's command after review and records whether it succeeded.
+/// Successful accepts are written to the decision log, and files filed under
+/// `base_path` are added to the content index.
+fn accept_entry(
+    queue: &Queue,
+    journal: &Journal,
+    decisions: &DecisionLog,
+    executor: &MoveExecutor,
+    config: Option<&Config>,
+    entry: &QueueEntry,
+) -> Result<QueueEntry, Box<dyn std::error::Error>> {
+    let decision = decision_for(entry, Action::Accept);
+    let (updated, destination) = apply_entry(queue, journal, executor, entry, AppliedBy::Review)?;
+    if updated.status == Status::Accepted {
+        if let Err(e) = decisions.record(&decision) {
+            log::error!("Failed to record decision on #{}: {}", entry.id, e);
+        }
+    }
+    if let (Some(config), Some(destination)) = (config, destination) {
+        if destination.starts_with(&config.base_path) {
+            if let Err(e) = index_filed(&data_dir()?, &destination) {
+                log::warn!("Could not index {}: {}", destination.display(), e);
+            }
+        }
+    }
+    Ok(updated)
+}
+
+/// Executes an entry's command and marks it accepted, or failed with the error.
+/// Applied moves and copies are written to the undo journal. Returns the updated
+/// entry and where the file was moved or copied to, if anywhere.
+pub fn apply_entry(
+    queue: &Queue,
+    journal: &Journal,
+    executor: &MoveExecutor,
+    entry: &QueueEntry,
+    applied_by: AppliedBy,
+) -> Result<(QueueEntry, Option<PathBuf>), Box<dyn std::error::Error>> {
+    let mut new_path = None;
+    let updated = match execute_command(&entry.command, executor) {
+        Ok(applied) => {
+            if let (Some(source), Some((operation, destination))) =
+                (&entry.command.file_path, applied)
+            {
+                let recorded = match operation {
+                    Operation::Move => {
+                        journal.record_move(Some(entry.id), source, &destination, applied_by)
+                    }
+                    Operation::Copy => {
+                        journal.record_copy(Some(entry.id), source, &destination, applied_by)
+                    }
+                };
+                if let Err(e) = recorded {
+                    log::error!("Failed to journal #{}: {}", entry.id, e);
+                }
+                new_path = Some(destination);
+            }
+            queue.set_status(entry.id, Status::Accepted, None)?
+        }
+        Err(e) => queue.set_status(entry.id, Status::Failed, Some(e.to_string()))?,
+    };
+    Ok((updated, new_path))
+}
+
+/// Resolves the selection's ids and globs against the pending suggestions.
+fn select_pending(
+    queue: &Queue,
+    selection: &Selection,
+) -> Result<Vec<QueueEntry>, Box<dyn std::error::Error>> {
+    let mut ids = Vec::new();
+    let mut patterns = Vec::new();
+    for target in &selection.targets {
+        match target.parse::<u64>() {
+            Ok(id) => ids.push(id),
+            Err(_) => patterns.push(Pattern::new(target)?),
+        }
+    }
+
+    let pending = queue.pending()?;
+    for id in &ids {
+        if !pending.iter().any(|entry| entry.id == *id) {
+            return Err(format!("No pending suggestion with id {}", id).into());
+        }
+    }
+
+    let selected = pending
+        .into_iter()
+        .filter(|entry| {
+            let path = entry.command.file_path.as_deref().unwrap_or(Path::new(""));
+            selection.all
+                || ids.contains(&entry.id)
+                || patterns.iter().any(|pattern| {
+                    pattern.matches_path(path)
+                        || path
+                            .file_name()
+                            .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+                })
+        })
+        .filter(|entry| match &selection.reason {
+            Some(reason) => entry
+                .command
+                .reason
+                .as_ref()
+                .is_some_and(|r| r.matches(reason)),
+            None => true,
+        })
+        .collect();
+    Ok(selected)
+}
+
+fn display_destination(command: &Command) -> String {
+    match command.command {
+        CommandEnum::Trash => "(trash)".to_string(),
+        CommandEnum::Duplicate => format!("(duplicate of {})", display_path(&command.destination)),
+        CommandEnum::Tag => format!("[{}]", command.tags.join(", ")),
+        CommandEnum::Copy => format!("{} (copy)", display_target(command)),
+        _ => display_target(command),
+    }
+}
+
+/// The destination, including the new name when the file is renamed.
+pub fn display_target(command: &Command) -> String {
+    match (&command.destination, &command.rename) {
+        (Some(destination), Some(rename)) => destination.join(rename).display().to_string(),
+        _ => display_path(&command.destination),
+    }
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map_or("-".to_string(), |p| p.display().to_string())
+}
+
+/// Returns what was done to the file and where it ended up, if it was moved or copied.
+fn execute_command(
+    command: &Command,
+    executor: &MoveExecutor,
+) -> Result<Option<(Operation, PathBuf)>, Box<dyn std::error::Error>> {
+    let file_path = || command.file_path.as_ref().ok_or("Command has no file path");
+    let target = || -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let file_path = file_path()?;
+        let destination = command
+            .destination
+            .as_ref()
+            .ok_or("Command has no destination")?;
+        let file_name = match &command.rename {
+            Some(rename) => rename.as_str(),
+            None => get_file_name(file_path.to_str().ok_or("Invalid path")?)
+                .ok_or("Invalid file name")?,
+        };
+        Ok(destination.join(file_name))
+    };
+    let outcome = match command.command {
+        CommandEnum::Move => (
+            Operation::Move,
+            executor.move_file(file_path()?, &target()?)?,
+        ),
+        CommandEnum::Copy => (
+            Operation::Copy,
+            executor.copy_file(file_path()?, &target()?)?,
+        ),
+        CommandEnum::Trash | CommandEnum::Duplicate => (
+            Operation::Move,
+            MoveOutcome::Moved(trash_file(file_path()?)?),
+        ),
+        CommandEnum::Tag => {
+            tag_file(file_path()?, &command.tags)?;
+            return Ok(None);
+        }
+        CommandEnum::Skip => return Ok(None),
+        CommandEnum::Indeterminate => return Err("The suggestion has no destination".into()),
+    };
+    match outcome {
+        (operation, MoveOutcome::Moved(new_file_path)) => Ok(Some((operation, new_file_path))),
+        (_, MoveOutcome::Skipped(existing)) => {
+            Err(format!("Skipped, {} already exists", existing.display()).into())
+        }
+    }
+}
+
+pub fn executor_for(config: Option<&Config>) -> MoveExecutor {
+    MoveExecutor::new(config.map(|c| c.collision_policy).unwrap_or_default())
+}
+
+/// Whether `name` names a file in a folder as it is: not empty, `.` or `..`, and without
+/// separators or NUL. `:` is also refused on macOS, where Finder shows it as `/`.
+fn is_file_name(name: &str) -> bool {
+    let forbidden: &[char] = if cfg!(target_os = "macos") {
+        &['\0', ':']
+    } else {
+        &['\0']
+    };
+    !name.contains(forbidden) && Path::new(name).file_name() == Some(name.as_ref())
+}
+
 fn get_file_name(path: &str) -> Option<&str> {
     Path::new(path).file_name()?.to_str()
 }
-fn clear_file(path: &str) -> Result<(), Error> {
-    File::create(path)?;
-    Ok(())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reason_filters() {
+        let cases = [
+            ("course-code", true),
+            ("Course-Code", true),
+            ("chatgpt", true),
+            ("learned", true),
+            ("duplicate", true),
+            ("rule", true),
+            ("rule:receipts", true),
+            ("RULE:receipts", true),
+            ("rule:", false),
+            ("receipts", false),
+            ("learned:receipts", false),
+            ("", false),
+        ];
+        for (filter, valid) in cases {
+            assert_eq!(
+                ReasonEnum::parse_filter(filter).is_ok(),
+                valid,
+                "{}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn rename_must_be_a_file_name() {
+        let cases = [
+            ("notes.pdf", true),
+            ("CS246 A1 (v2).pdf", true),
+            (".notes", true),
+            ("", false),
+            (".", false),
+            ("..", false),
+            ("a/b.pdf", false),
+            ("/notes.pdf", false),
+            ("notes.pdf/", false),
+            ("no\0tes.pdf", false),
+            ("10:30.pdf", !cfg!(target_os = "macos")),
+        ];
+        for (name, valid) in cases {
+            assert_eq!(is_file_name(name), valid, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn reason_matches_filters() {
+        let rule = ReasonEnum::Rule("receipts".to_string());
+        assert!(rule.matches("rule"));
+        assert!(rule.matches("rule:receipts"));
+        assert!(!rule.matches("rule:invoices"));
+        assert!(!rule.matches("learned"));
+        assert!(ReasonEnum::CourseCode.matches("course-code"));
+        assert!(!ReasonEnum::CourseCode.matches("course"));
+    }
 }
@@ -0,0 +1,249 @@
+use crate::auto_apply::AutoApply;
+use crate::setup::Config;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Names for the classifiers in a root's `classifiers` list.
+pub const CLASSIFIER_KEYS: [&str; 3] = ["course-code", "learned", "llm"];
+
+/// A folder nimbus watches for new files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchRoot {
+    /// May start with `~`.
+    pub path: PathBuf,
+    /// Also watch the folders inside it.
+    #[serde(default = "default_recursive")]
+    pub recursive: bool,
+    /// Globs on the file name skipped in this folder, on top of `readiness.ignore`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Which classifiers may place files from this folder: `course-code`, `learned` and
+    /// `llm`. All of them when unset. Rules always apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classifiers: Option<Vec<String>>,
+    /// Replaces the config-wide `auto_apply` policies for files from this folder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_apply: Option<Vec<AutoApply>>,
+}
+
+fn default_recursive() -> bool {
+    true
+}
+
+impl WatchRoot {
+    pub fn new(path: PathBuf) -> WatchRoot {
+        WatchRoot {
+            path,
+            recursive: true,
+            ignore: Vec::new(),
+            classifiers: None,
+            auto_apply: None,
+        }
+    }
+}
+
+/// The folders to watch: the `watch` list, or just `download_path` without one.
+pub fn watch_roots(config: &Config) -> Vec<WatchRoot> {
+    if config.watch.is_empty() {
+        return vec![WatchRoot::new(config.download_path.clone())];
+    }
+    config
+        .watch
+        .iter()
+        .map(|root| WatchRoot {
+            path: expand_home(&root.path),
+            ..root.clone()
+        })
+        .collect()
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The key a root's `classifiers` list uses for a classifier, e.g. `course-code`.
+pub fn classifier_key(name: &str) -> String {
+    name.to_lowercase().replace(' ', "-")
+}
+
+/// A watch root with its ignore globs compiled.
+pub struct CompiledRoot {
+    pub root: WatchRoot,
+    ignore: Vec<Pattern>,
+}
+
+impl CompiledRoot {
+    /// Fails on an invalid glob or an unknown classifier.
+    pub fn new(root: WatchRoot) -> Result<CompiledRoot, String> {
+        let ignore = root
+            .ignore
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid ignore glob for {}: {}", root.path.display(), e))?;
+        for key in root.classifiers.iter().flatten() {
+            if !CLASSIFIER_KEYS.contains(&key.as_str()) {
+                return Err(format!(
+                    "Unknown classifier {} for {}, expected one of {}",
+                    key,
+                    root.path.display(),
+                    CLASSIFIER_KEYS.join(", ")
+                ));
+            }
+        }
+        Ok(CompiledRoot { root, ignore })
+    }
+
+    /// Whether a file under this root is skipped: its name matches an ignore glob, or it
+    /// is in a subfolder of a root that is not recursive.
+    pub fn ignores(&self, path: &Path) -> bool {
+        if !self.root.recursive && path.parent() != Some(self.root.path.as_path()) {
+            return true;
+        }
+        path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            self.ignore.iter().any(|pattern| pattern.matches(&name))
+        })
+    }
+
+    pub fn allows(&self, classifier_name: &str) -> bool {
+        self.root.classifiers.as_ref().is_none_or(|keys| {
+            keys.iter()
+                .any(|key| *key == classifier_key(classifier_name))
+        })
+    }
+}
+
+pub fn compile_roots(config: &Config) -> Result<Vec<CompiledRoot>, String> {
+    watch_roots(config)
+        .into_iter()
+        .map(CompiledRoot::new)
+        .collect()
+}
+
+/// The innermost root `path` is in.
+pub fn root_for<'a>(roots: &'a [CompiledRoot], path: &Path) -> Option<&'a CompiledRoot> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(&root.root.path))
+        .max_by_key(|root| root.root.path.components().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::{Classifier, CourseCodeClassifier, LlmClassifier, ModelClassifier};
+    use crate::llm::{ChatClient, LlmConfig};
+    use std::collections::HashMap;
+
+    fn root(
+        path: &str,
+        recursive: bool,
+        ignore: &[&str],
+        classifiers: Option<&[&str]>,
+    ) -> CompiledRoot {
+        CompiledRoot::new(WatchRoot {
+            path: PathBuf::from(path),
+            recursive,
+            ignore: ignore.iter().map(|glob| glob.to_string()).collect(),
+            classifiers: classifiers.map(|keys| keys.iter().map(|key| key.to_string()).collect()),
+            auto_apply: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn picks_the_innermost_root() {
+        let roots = [
+            root("/home/user/Downloads", true, &[], None),
+            root("/home/user/Downloads/School", true, &[], None),
+            root("/home/user/Desktop", false, &[], None),
+        ];
+        let cases = [
+            ("/home/user/Downloads/a.pdf", Some("/home/user/Downloads")),
+            (
+                "/home/user/Downloads/School/a.pdf",
+                Some("/home/user/Downloads/School"),
+            ),
+            (
+                "/home/user/Downloads/School/CS246/a.pdf",
+                Some("/home/user/Downloads/School"),
+            ),
+            (
+                "/home/user/Downloads/Schoolwork/a.pdf",
+                Some("/home/user/Downloads"),
+            ),
+            ("/home/user/Desktop/a.pdf", Some("/home/user/Desktop")),
+            ("/home/user/Documents/a.pdf", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                root_for(&roots, Path::new(path)).map(|root| root.root.path.as_path()),
+                expected.map(Path::new),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_subfolders_of_non_recursive_roots_and_ignored_names() {
+        let flat = root("/home/user/Desktop", false, &["*.lnk"], None);
+        let deep = root("/home/user/Downloads", true, &["*.torrent"], None);
+        let cases = [
+            (&flat, "/home/user/Desktop/a.pdf", false),
+            (&flat, "/home/user/Desktop/Screenshots/a.png", true),
+            (&flat, "/home/user/Desktop/shortcut.lnk", true),
+            (&deep, "/home/user/Downloads/a.pdf", false),
+            (&deep, "/home/user/Downloads/School/CS246/a.pdf", false),
+            (&deep, "/home/user/Downloads/School/ubuntu.torrent", true),
+        ];
+        for (root, path, expected) in cases {
+            assert_eq!(root.ignores(Path::new(path)), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn every_classifier_has_a_key() {
+        let client = ChatClient::new(&LlmConfig::default()).unwrap();
+        let classifiers: Vec<Box<dyn Classifier>> = vec![
+            Box::new(CourseCodeClassifier::new(Vec::new()).unwrap()),
+            Box::new(ModelClassifier::new(PathBuf::new(), HashMap::new())),
+            Box::new(LlmClassifier::new(client, Vec::new())),
+        ];
+        let keys: Vec<String> = classifiers
+            .iter()
+            .map(|classifier| classifier_key(classifier.name()))
+            .collect();
+        assert_eq!(keys, CLASSIFIER_KEYS);
+
+        let only_llm = root("/home/user/Downloads", true, &[], Some(&["llm"]));
+        let allowed: Vec<&str> = classifiers
+            .iter()
+            .map(|classifier| classifier.name())
+            .filter(|name| only_llm.allows(name))
+            .collect();
+        assert_eq!(allowed, ["LLM"]);
+        let all = root("/home/user/Downloads", true, &[], None);
+        assert!(classifiers
+            .iter()
+            .all(|classifier| all.allows(classifier.name())));
+    }
+
+    #[test]
+    fn refuses_unknown_classifiers_and_invalid_globs() {
+        let mut root = WatchRoot::new(PathBuf::from("/home/user/Downloads"));
+        root.classifiers = Some(vec!["chatgpt".to_string()]);
+        let error = CompiledRoot::new(root.clone()).err().unwrap();
+        assert!(error.contains("Unknown classifier chatgpt"), "{}", error);
+
+        root.classifiers = None;
+        root.ignore = vec!["[".to_string()];
+        let error = CompiledRoot::new(root).err().unwrap();
+        assert!(error.contains("Invalid ignore glob"), "{}", error);
+    }
+}
@@ -0,0 +1,523 @@
+use crate::monitor::File;
+use crate::naming::{propose_name, NameContext};
+use crate::review::{Command, CommandEnum, ReasonEnum};
+use glob::Pattern;
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// A user-defined rule. Rules are tried in order before course matching and the
+/// first one whose conditions all hold decides what happens to the file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    #[serde(rename = "match", default)]
+    pub conditions: Conditions,
+    pub action: RuleAction,
+    /// Rename template for `move` and `copy`, e.g. `receipt_{date:%Y%m%d}.{ext}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+}
+
+/// Every condition that is set must hold. An empty `match` matches every file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Conditions {
+    /// Glob on the file name, e.g. `*receipt*`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    /// Regular expression on the file name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// Any of these extensions, without the dot. Case-insensitive.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// MIME type guessed from the extension, e.g. `application/pdf` or `image/*`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    /// Host of the origin or referrer URL. `uwaterloo.ca` also matches `learn.uwaterloo.ca`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Glob on the path of the origin or referrer URL, e.g. `/invoices/*`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url_path: Option<String>,
+    /// Size bounds in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Age bounds in days, from the file's modification time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_age_days: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RuleAction {
+    /// Relative destinations are under `base_path`; `~/` is the home directory.
+    Move {
+        destination: PathBuf,
+    },
+    Copy {
+        destination: PathBuf,
+    },
+    /// Gives the file a new name where it is. `to` is a rename template.
+    Rename {
+        to: String,
+    },
+    DeleteToTrash,
+    Skip,
+    Tag {
+        tags: Vec<String>,
+    },
+}
+
+/// The configured rules with their globs and regexes compiled.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+    base_path: PathBuf,
+}
+
+struct CompiledRule {
+    rule: Rule,
+    glob: Option<Pattern>,
+    regex: Option<Regex>,
+    url_path: Option<Pattern>,
+}
+
+/// `days` as a duration. Absurdly large bounds saturate instead of wrapping around.
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+}
+
+impl RuleSet {
+    /// Fails on the first rule with an invalid glob or regex.
+    pub fn new(rules: &[Rule], base_path: &Path) -> Result<RuleSet, Box<dyn std::error::Error>> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let invalid = |e: &dyn std::fmt::Display| format!("Rule {}: {}", rule.name, e);
+                let conditions = &rule.conditions;
+                Ok(CompiledRule {
+                    rule: rule.clone(),
+                    glob: conditions
+                        .glob
+                        .as_deref()
+                        .map(Pattern::new)
+                        .transpose()
+                        .map_err(|e| invalid(&e))?,
+                    regex: conditions
+                        .regex
+                        .as_deref()
+                        .map(Regex::new)
+                        .transpose()
+                        .map_err(|e| invalid(&e))?,
+                    url_path: conditions
+                        .url_path
+                        .as_deref()
+                        .map(Pattern::new)
+                        .transpose()
+                        .map_err(|e| invalid(&e))?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(RuleSet {
+            rules,
+            base_path: base_path.to_path_buf(),
+        })
+    }
+
+    /// The command produced by the first rule that matches the file, if any. `Some(None)`
+    /// when that rule has nothing left to do: a skip, or a rename to the name the file
+    /// already has.
+    pub fn evaluate(&self, file: &File) -> Option<Option<Command>> {
+        let rule = self.rules.iter().find(|rule| rule.matches(file))?;
+        log::info!("Rule {} matched {}", rule.rule.name, file.name);
+        Some(self.command_for(&rule.rule, file))
+    }
+
+    fn command_for(&self, rule: &Rule, file: &File) -> Option<Command> {
+        let mut command = Command {
+            file_path: Some(file.path.clone()),
+            command: CommandEnum::Skip,
+            destination: None,
+            reason: Some(ReasonEnum::Rule(rule.name.clone())),
+            rename: None,
+            confidence: Some(1.0),
+            tags: Vec::new(),
+        };
+        let context = NameContext::for_file(&file.path, None, None);
+        match &rule.action {
+            RuleAction::Move { destination } => {
+                command.command = CommandEnum::Move;
+                command.destination = Some(self.resolve(destination));
+                command.rename = propose_name(rule.rename.as_deref(), &context, &file.name);
+            }
+            RuleAction::Copy { destination } => {
+                command.command = CommandEnum::Copy;
+                command.destination = Some(self.resolve(destination));
+                command.rename = propose_name(rule.rename.as_deref(), &context, &file.name);
+            }
+            RuleAction::Rename { to } => {
+                command.command = CommandEnum::Move;
+                command.destination = file.path.parent().map(Path::to_path_buf);
+                command.rename = propose_name(Some(to), &context, &file.name);
+                if command.rename.is_none() {
+                    log::info!(
+                        "{} already has the name rule {} gives it",
+                        file.name,
+                        rule.name
+                    );
+                    return None;
+                }
+            }
+            RuleAction::DeleteToTrash => command.command = CommandEnum::Trash,
+            RuleAction::Skip => {
+                log::info!("Rule {} leaves {} alone", rule.name, file.name);
+                return None;
+            }
+            RuleAction::Tag { tags } => {
+                command.command = CommandEnum::Tag;
+                command.tags = tags.clone();
+            }
+        }
+        Some(command)
+    }
+
+    fn resolve(&self, destination: &Path) -> PathBuf {
+        if let (Ok(rest), Some(home)) = (destination.strip_prefix("~"), dirs::home_dir()) {
+            return home.join(rest);
+        }
+        // Joining an absolute path replaces the base.
+        self.base_path.join(destination)
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, file: &File) -> bool {
+        let conditions = &self.rule.conditions;
+        let extension = file
+            .path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if self.glob.as_ref().is_some_and(|g| !g.matches(&file.name))
+            || self.regex.as_ref().is_some_and(|r| !r.is_match(&file.name))
+        {
+            return false;
+        }
+        if !conditions.extensions.is_empty()
+            && !conditions
+                .extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+        {
+            return false;
+        }
+        if let Some(mime) = &conditions.mime {
+            if !mime_matches(mime, guess_mime(&extension)) {
+                return false;
+            }
+        }
+        if conditions.host.is_some() || self.url_path.is_some() {
+            let urls: Vec<Url> = [&file.url, &file.referrer]
+                .into_iter()
+                .flatten()
+                .filter_map(|url| Url::parse(url).ok())
+                .collect();
+            let host_matches = |url: &Url| match (&conditions.host, url.host_str()) {
+                (None, _) => true,
+                (Some(wanted), Some(host)) => host_matches(host, wanted),
+                (Some(_), None) => false,
+            };
+            let path_matches = |url: &Url| {
+                self.url_path
+                    .as_ref()
+                    .is_none_or(|pattern| pattern.matches(url.path()))
+            };
+            if !urls
+                .iter()
+                .any(|url| host_matches(url) && path_matches(url))
+            {
+                return false;
+            }
+        }
+        let needs_metadata = conditions.min_size.is_some()
+            || conditions.max_size.is_some()
+            || conditions.min_age_days.is_some()
+            || conditions.max_age_days.is_some();
+        if needs_metadata {
+            let Ok(metadata) = std::fs::metadata(&file.path) else {
+                return false;
+            };
+            let size = metadata.len();
+            if conditions.min_size.is_some_and(|min| size < min)
+                || conditions.max_size.is_some_and(|max| size > max)
+            {
+                return false;
+            }
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+            if conditions.min_age_days.is_some_and(|min| age < days(min))
+                || conditions.max_age_days.is_some_and(|max| age > days(max))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// `wanted` may end in `/*` to match a whole family, like `image/*`.
+fn mime_matches(wanted: &str, actual: &str) -> bool {
+    match wanted.strip_suffix("/*") {
+        Some(family) => actual
+            .split('/')
+            .next()
+            .is_some_and(|f| f.eq_ignore_ascii_case(family)),
+        None => wanted.eq_ignore_ascii_case(actual),
+    }
+}
+
+/// MIME type for a lowercase file extension.
+pub fn guess_mime(extension: &str) -> &'static str {
+    match extension {
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "txt" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "ics" => "text/calendar",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        "dmg" => "application/x-apple-diskimage",
+        "pkg" => "application/x-newton-compatible-pkg",
+        "exe" | "msi" => "application/x-msdownload",
+        "deb" => "application/vnd.debian.binary-package",
+        "rpm" => "application/x-rpm",
+        "appimage" => "application/vnd.appimage",
+        "iso" => "application/x-iso9660-image",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "heic" => "image/heic",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether `host` is `wanted` or one of its subdomains.
+pub fn host_matches(host: &str, wanted: &str) -> bool {
+    let wanted = wanted.to_ascii_lowercase();
+    host == wanted || host.ends_with(&format!(".{}", wanted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(yaml: &str) -> RuleSet {
+        let rules: Vec<Rule> = serde_yaml::from_str(yaml).unwrap();
+        RuleSet::new(&rules, Path::new("/base")).unwrap()
+    }
+
+    fn file(path: &str, url: Option<&str>) -> File {
+        let path = PathBuf::from(path);
+        File {
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            url: url.map(str::to_string),
+            referrer: None,
+            path,
+            text: None,
+        }
+    }
+
+    fn evaluate(rules: &RuleSet, path: &str) -> Option<Option<Command>> {
+        rules.evaluate(&file(path, None))
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = rules(
+            r#"
+- name: receipts
+  match: { glob: "*receipt*", extensions: [pdf] }
+  action: { type: move, destination: Finance }
+- name: pdfs
+  match: { extensions: [PDF] }
+  action: { type: copy, destination: ~/Documents }
+- name: installers
+  match: { regex: '\.(dmg|pkg)$' }
+  action: { type: delete-to-trash }
+"#,
+        );
+        let cases = [
+            ("/dl/receipt-jan.pdf", Some(("receipts", CommandEnum::Move))),
+            ("/dl/receipt-jan.png", None),
+            ("/dl/notes.pdf", Some(("pdfs", CommandEnum::Copy))),
+            ("/dl/Zoom.pkg", Some(("installers", CommandEnum::Trash))),
+            ("/dl/photo.jpg", None),
+        ];
+        for (path, expected) in cases {
+            let command = evaluate(&rules, path).map(|command| command.unwrap());
+            let actual = command.map(|command| match command.reason {
+                Some(ReasonEnum::Rule(name)) => (name, command.command),
+                reason => panic!("unexpected reason {:?}", reason),
+            });
+            assert_eq!(
+                actual,
+                expected.map(|(name, action)| (name.to_string(), action)),
+                "path: {:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn destinations_are_under_base_path_unless_absolute() {
+        let rules = rules(
+            r#"
+- name: relative
+  match: { glob: "a*" }
+  action: { type: move, destination: Finance/Receipts }
+- name: absolute
+  match: { glob: "b*" }
+  action: { type: move, destination: /srv/shared }
+"#,
+        );
+        let destination = |path| evaluate(&rules, path).unwrap().unwrap().destination;
+        assert_eq!(
+            destination("/dl/a.pdf"),
+            Some(PathBuf::from("/base/Finance/Receipts"))
+        );
+        assert_eq!(destination("/dl/b.pdf"), Some(PathBuf::from("/srv/shared")));
+    }
+
+    #[test]
+    fn matches_origin_host_and_path() {
+        let rules = rules(
+            r#"
+- name: invoices
+  match: { host: uwaterloo.ca, url_path: "/invoices/*" }
+  action: { type: tag, tags: [invoice] }
+"#,
+        );
+        let cases = [
+            (Some("https://quest.uwaterloo.ca/invoices/2024.pdf"), true),
+            (Some("https://uwaterloo.ca/invoices/2024.pdf"), true),
+            (Some("https://quest.uwaterloo.ca/grades/2024.pdf"), false),
+            (Some("https://notuwaterloo.ca/invoices/2024.pdf"), false),
+            (None, false),
+        ];
+        for (url, matched) in cases {
+            let command = rules.evaluate(&file("/dl/2024.pdf", url));
+            assert_eq!(command.is_some(), matched, "url: {:?}", url);
+        }
+    }
+
+    #[test]
+    fn rename_keeps_the_file_where_it_is() {
+        let rules = rules(
+            r#"
+- name: scans
+  match: { glob: "scan*" }
+  action: { type: rename, to: "Scan {index:03}.{ext}" }
+"#,
+        );
+        let command = evaluate(&rules, "/dl/scan_7.pdf").unwrap().unwrap();
+        assert_eq!(command.command, CommandEnum::Move);
+        assert_eq!(command.destination, Some(PathBuf::from("/dl")));
+        assert_eq!(command.rename.as_deref(), Some("Scan 007.pdf"));
+    }
+
+    #[test]
+    fn rename_to_the_current_name_suggests_nothing() {
+        let rules = rules(
+            r#"
+- name: scans
+  match: { glob: "[Ss]can*" }
+  action: { type: rename, to: "Scan {index:03}.{ext}" }
+- name: everything
+  action: { type: skip }
+"#,
+        );
+        // The rule matched, so later rules and classifiers do not get the file either.
+        assert!(matches!(evaluate(&rules, "/dl/Scan 007.pdf"), Some(None)));
+    }
+
+    #[test]
+    fn skip_suggests_nothing() {
+        let rules = rules(
+            r#"
+- name: keep
+  match: { extensions: [iso] }
+  action: { type: skip }
+- name: everything
+  action: { type: delete-to-trash }
+"#,
+        );
+        assert!(matches!(evaluate(&rules, "/dl/ubuntu.iso"), Some(None)));
+        assert!(matches!(
+            evaluate(&rules, "/dl/notes.pdf"),
+            Some(Some(Command {
+                command: CommandEnum::Trash,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn matches_age_in_days() {
+        let dir = crate::testing::temp_dir("rules-age");
+        let path = dir.join("old.pdf");
+        std::fs::write(&path, "old").unwrap();
+        let ten_days_ago = SystemTime::now() - days(10);
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(ten_days_ago))
+            .unwrap();
+        let cases = [
+            ("min_age_days: 7", true),
+            ("min_age_days: 30", false),
+            ("max_age_days: 7", false),
+            ("max_age_days: 30", true),
+            ("min_age_days: 7, max_age_days: 30", true),
+            // 2^32 days used to wrap around to zero.
+            ("min_age_days: 4294967296", false),
+            ("min_age_days: 18446744073709551615", false),
+            ("max_age_days: 18446744073709551615", true),
+        ];
+        for (conditions, expected) in cases {
+            let rules = rules(&format!(
+                "- name: old\n  match: {{ {} }}\n  action: {{ type: delete-to-trash }}",
+                conditions
+            ));
+            assert_eq!(
+                evaluate(&rules, path.to_str().unwrap()).is_some(),
+                expected,
+                "{}",
+                conditions
+            );
+        }
+    }
+}
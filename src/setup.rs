@@ -1,4 +1,12 @@
-use chrono::{expect, Datelike, Local};
+use crate::auto_apply::AutoApply;
+use crate::executor::CollisionPolicy;
+use crate::extract::ExtractionConfig;
+use crate::llm::LlmConfig;
+use crate::readiness::ReadinessConfig;
+use crate::roots::WatchRoot;
+use crate::rules::Rule;
+use crate::subfolders::{default_subfolders, scaffold, Subfolder};
+use chrono::{Datelike, Local};
 use dirs::download_dir;
 use promkit::{preset::QuerySelect, preset::Readline, preset::Select};
 use reqwest;
@@ -12,10 +20,28 @@ use std::io;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct Course {
     pub name: String,
     pub description: String,
+    /// Cross-listed codes (`CO250`) or nicknames (`ECE 2xx Circuits`) for the course.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Words that hint at the course, like a prof's name. The weakest kind of evidence.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Regular expressions tried against the file name and origin URLs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+    /// LEARN (D2L) org-unit IDs, as in `/d2l/le/content/912345/`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub d2l_ids: Vec<u64>,
+    /// Replaces the config-wide `subfolders` for this course.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subfolders: Option<Vec<Subfolder>>,
+    /// Replaces the config-wide `rename_template` for this course.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename_template: Option<String>,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
@@ -28,8 +54,38 @@ pub struct Config {
     pub coop: bool,
     pub courses: Vec<Course>,
     api_key: String,
+    /// What to do when a file with the same name is already at the destination.
+    #[serde(default)]
+    pub collision_policy: CollisionPolicy,
+    /// Chat-completion endpoint used when the course code is not enough.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm: Option<LlmConfig>,
+    /// How much of a document's text is read when classifying it.
+    #[serde(default)]
+    pub extraction: ExtractionConfig,
+    /// Tried in order before course matching; the first match wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// Folders inside every course directory. Course folders stay flat without any, as
+    /// in configs written before subfolders existed; `nimbus config` adds the defaults.
+    #[serde(default)]
+    pub subfolders: Vec<Subfolder>,
+    /// New name for course files, e.g. `{course}_{category}_{index:02}.{ext}`.
+    /// Without one, files keep their names minus any browser ` (1)` suffix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename_template: Option<String>,
+    /// Moves that are applied without review. Any one policy matching is enough.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_apply: Vec<AutoApply>,
+    /// Folders to watch instead of just `download_path`, each with its own settings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watch: Vec<WatchRoot>,
+    /// Which files in the downloads folder are skipped or waited on until they settle.
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
 }
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
 struct CourseInfo {
     courseId: Option<String>,
     courseOfferNumber: Option<i32>,
@@ -52,8 +108,48 @@ struct CourseInfo {
     requirementsDescription: Option<String>,
 }
 
-pub async fn setup_nimbus() -> Result<(), Box<dyn Error>> {
-    if Path::new("config.yaml").exists() {
+const CONFIG_ENV_VAR: &str = "NIMBUS_CONFIG";
+const DATA_DIR_ENV_VAR: &str = "NIMBUS_DATA_DIR";
+
+/// Resolves where the config lives: the `--config` flag, then `$NIMBUS_CONFIG`,
+/// then `<config dir>/nimbus/config.yaml` (e.g. `~/.config/nimbus/config.yaml`).
+pub fn config_path(flag: Option<PathBuf>) -> Result<PathBuf, io::Error> {
+    if let Some(path) = flag {
+        return Ok(path);
+    }
+    if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    dirs::config_dir()
+        .map(|dir| dir.join("nimbus").join("config.yaml"))
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Could not determine a config directory. Pass --config or set {}",
+                CONFIG_ENV_VAR
+            ),
+        ))
+}
+
+/// Where nimbus keeps its state (the review queue and friends): `$NIMBUS_DATA_DIR`,
+/// then `<data dir>/nimbus` (e.g. `~/.local/share/nimbus`).
+pub fn data_dir() -> Result<PathBuf, io::Error> {
+    if let Some(path) = std::env::var_os(DATA_DIR_ENV_VAR).filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join("nimbus"))
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Could not determine a data directory. Set {}",
+                DATA_DIR_ENV_VAR
+            ),
+        ))
+}
+
+pub async fn setup_nimbus(config_path: &Path) -> Result<(), Box<dyn Error>> {
+    if config_path.exists() {
         log::info!("Config file exists");
         let mut continue_prompt = QuerySelect::new(['Y', 'N'], |text, items| -> Vec<String> {
             text.parse::<usize>()
@@ -75,14 +171,17 @@ pub async fn setup_nimbus() -> Result<(), Box<dyn Error>> {
         }
     }
     let config = parse_user_input().await?;
-    write_config(config.clone()).expect("Failed to save config");
-    log::info!("Saved config");
+    write_config(config.clone(), config_path).expect("Failed to save config");
+    log::info!("Saved config to {}", config_path.display());
+    match scaffold(&config) {
+        Ok(_) => log::info!("Created course directories"),
+        Err(e) => log::error!("Failed to create course directories: {}", e),
+    }
 
     Ok(())
 }
 
 async fn parse_user_input() -> Result<Config, Box<dyn Error>> {
-    let mut courses_map: HashMap<String, Option<String>> = HashMap::new();
     let mut config = Config::default();
     let default_download_path_buf = download_dir().unwrap();
     let default_download_path = default_download_path_buf.to_str().unwrap();
@@ -128,7 +227,7 @@ async fn parse_user_input() -> Result<Config, Box<dyn Error>> {
     let mut courses_prompt = Readline::default()
         .title("What courses are you taking this term? Please provide your answers in a comma separated list")
         .validator(
-            |text| is_valid_course_list(text),
+            is_valid_course_list,
             |text| format!("Must be a comma seperated list. Got {} instead", text),
         )
         .prompt()?;
@@ -146,7 +245,7 @@ async fn parse_user_input() -> Result<Config, Box<dyn Error>> {
         waterloo_api_key
     };
 
-    courses_map = generate_course_map(config.clone().api_key).await?;
+    let courses_map = generate_course_map(config.clone().api_key).await?;
     let download_path_input = download_path_prompt.run()?;
     config.download_path = if download_path_input.trim().is_empty() {
         default_download_path_buf
@@ -158,29 +257,48 @@ async fn parse_user_input() -> Result<Config, Box<dyn Error>> {
         .lines(4)
         .prompt()?;
     let current_term = term_prompt.run()?;
+    config.base_path = PathBuf::from(base_path_prompt.run()?);
     match create_term_directories(&current_term, config.clone().base_path) {
         Ok(_) => log::info!("Created term directories"),
         Err(e) => log::error!("Failed to create term directories: {}", e),
     }
-    config.base_path = PathBuf::from(base_path_prompt.run()?);
     config.start_year = start_year_prompt.run()?.parse().unwrap();
     config.end_year = end_year_prompt.run()?.parse().unwrap();
     config.coop = coop_prompt.run()?.parse().unwrap();
     config.courses = parse_course_list(courses_prompt.run()?, courses_map);
     config.current_term = current_term;
+    config.subfolders = default_subfolders();
     Ok(config)
 }
 
-pub fn read_config() -> Result<Config, io::Error> {
-    let contents = include_str!("../config.yaml");
-    let parsed_data: Config = serde_yaml::from_str(&contents)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+pub fn read_config(path: &Path) -> Result<Config, io::Error> {
+    let contents = fs::read_to_string(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No config found at {}. Run `nimbus config` to create one",
+                path.display()
+            ),
+        ),
+        _ => io::Error::new(
+            e.kind(),
+            format!("Failed to read config {}: {}", path.display(), e),
+        ),
+    })?;
+    let parsed_data: Config = serde_yaml::from_str(&contents).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid config {}: {}", path.display(), e),
+        )
+    })?;
     Ok(parsed_data)
 }
-fn write_config(config: Config) -> Result<(), io::Error> {
-    let yaml_string =
-        serde_yaml::to_string(&config).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    let mut file = File::create("config.yaml")?;
+fn write_config(config: Config, path: &Path) -> Result<(), io::Error> {
+    let yaml_string = serde_yaml::to_string(&config).map_err(io::Error::other)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
     file.write_all(yaml_string.as_bytes())?;
     Ok(())
 }
@@ -191,7 +309,7 @@ fn create_term_directories(current_term: &str, base_dir: PathBuf) -> Result<(),
 
     let curr_term_num = current_term
         .chars()
-        .nth(0)
+        .next()
         .and_then(|c| c.to_digit(10))
         .ok_or(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -224,8 +342,9 @@ fn parse_course_list(s: String, courses_map: HashMap<String, Option<String>>) ->
             Some(course_description) => courses.push(Course {
                 name: course_name,
                 description: course_description.clone(),
+                ..Default::default()
             }),
-            None => println!("{}: {}", course, "No description found"),
+            None => println!("{}: No description found", course),
         }
     }
     courses
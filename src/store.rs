@@ -0,0 +1,281 @@
+use fs2::FileExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// A record kept in a `SnapshotLog`, identified by a stable numeric id.
+pub trait Record: Serialize + DeserializeOwned + Clone {
+    fn id(&self) -> u64;
+}
+
+/// An append-only JSON-lines file.
+///
+/// Every read and write holds a lock on a sidecar lock file, so several processes
+/// (the daemon and the CLI) can use the same file at once.
+pub struct JsonLines<T> {
+    path: PathBuf,
+    lock_path: PathBuf,
+    record: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> JsonLines<T> {
+    /// Opens `<dir>/<name>.jsonl`, creating `dir` if needed.
+    pub fn open(dir: &Path, name: &str) -> io::Result<JsonLines<T>> {
+        fs::create_dir_all(dir)?;
+        Ok(JsonLines {
+            path: dir.join(format!("{}.jsonl", name)),
+            lock_path: dir.join(format!("{}.lock", name)),
+            record: PhantomData,
+        })
+    }
+
+    pub fn append(&self, record: &T) -> io::Result<()> {
+        let _lock = self.lock_exclusive()?;
+        self.append_locked(record)
+    }
+
+    /// Every readable line, oldest first. Unreadable lines are logged and skipped.
+    pub fn all(&self) -> io::Result<Vec<T>> {
+        let _lock = self.lock_shared()?;
+        self.read_locked()
+    }
+
+    fn read_locked(&self) -> io::Result<Vec<T>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<T>(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => log::warn!(
+                    "Skipping unreadable line {} of {}: {}",
+                    index + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(records)
+    }
+
+    fn append_locked(&self, record: &T) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        file.sync_data()
+    }
+
+    /// Replaces the whole file with `records`.
+    fn rewrite_locked<'a>(&self, records: impl Iterator<Item = &'a T>) -> io::Result<()>
+    where
+        T: 'a,
+    {
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut file = File::create(&tmp_path)?;
+        for record in records {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp_path, &self.path)
+    }
+
+    fn sync_locked(&self) -> io::Result<()> {
+        match File::open(&self.path) {
+            Ok(file) => file.sync_all(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lock_exclusive(&self) -> io::Result<File> {
+        let file = self.lock_file()?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    fn lock_shared(&self) -> io::Result<File> {
+        let file = self.lock_file()?;
+        file.lock_shared()?;
+        Ok(file)
+    }
+
+    // The lock is released when the returned handle is dropped.
+    fn lock_file(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.lock_path)
+    }
+}
+
+/// An append-only JSON-lines log of record snapshots; the latest snapshot for an id wins.
+/// The log is rewritten without stale snapshots once enough of them pile up.
+pub struct SnapshotLog<T> {
+    lines: JsonLines<T>,
+}
+
+// Rewrite the log once it holds this many superseded snapshots.
+const COMPACT_THRESHOLD: usize = 256;
+
+impl<T: Record> SnapshotLog<T> {
+    /// Opens `<dir>/<name>.jsonl`, creating `dir` if needed.
+    pub fn open(dir: &Path, name: &str) -> io::Result<SnapshotLog<T>> {
+        Ok(SnapshotLog {
+            lines: JsonLines::open(dir, name)?,
+        })
+    }
+
+    /// Builds a record from the next free id and appends it.
+    pub fn insert<F: FnOnce(u64) -> T>(&self, make: F) -> io::Result<T> {
+        let _lock = self.lines.lock_exclusive()?;
+        let (records, _) = self.load()?;
+        let record = make(records.keys().next_back().map_or(1, |id| id + 1));
+        self.lines.append_locked(&record)?;
+        Ok(record)
+    }
+
+    /// All records, oldest first.
+    pub fn all(&self) -> io::Result<Vec<T>> {
+        let _lock = self.lines.lock_shared()?;
+        let (records, _) = self.load()?;
+        Ok(records.into_values().collect())
+    }
+
+    pub fn get(&self, id: u64) -> io::Result<Option<T>> {
+        let _lock = self.lines.lock_shared()?;
+        let (mut records, _) = self.load()?;
+        Ok(records.remove(&id))
+    }
+
+    /// Applies `change` to the latest snapshot of `id` and appends the result.
+    pub fn modify<F: FnOnce(&mut T)>(&self, id: u64, change: F) -> io::Result<T> {
+        let _lock = self.lines.lock_exclusive()?;
+        let (mut records, superseded) = self.load()?;
+        let mut record = records.remove(&id).ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No record with id {} in {}", id, self.lines.path.display()),
+        ))?;
+        change(&mut record);
+        check_id(id, &record)?;
+        self.store(records, superseded, vec![record])
+            .map(|mut changed| changed.remove(0))
+    }
+
+    /// Applies `change` to every record `matches` selects and returns the changed records.
+    pub fn modify_where<P, F>(&self, matches: P, mut change: F) -> io::Result<Vec<T>>
+    where
+        P: Fn(&T) -> bool,
+        F: FnMut(&mut T),
+    {
+        let _lock = self.lines.lock_exclusive()?;
+        let (mut records, superseded) = self.load()?;
+        let ids: Vec<u64> = records
+            .values()
+            .filter(|record| matches(record))
+            .map(Record::id)
+            .collect();
+        let mut changed = Vec::new();
+        for id in ids {
+            let Some(mut record) = records.remove(&id) else {
+                continue;
+            };
+            change(&mut record);
+            check_id(id, &record)?;
+            changed.push(record);
+        }
+        self.store(records, superseded, changed)
+    }
+
+    /// Applies `change` to the first record `matches` selects, or inserts the record `make`
+    /// builds from the next free id if there is none. Returns the record and whether it is new.
+    pub fn upsert<P, F, M>(&self, matches: P, change: F, make: M) -> io::Result<(T, bool)>
+    where
+        P: Fn(&T) -> bool,
+        F: FnOnce(&mut T),
+        M: FnOnce(u64) -> T,
+    {
+        let _lock = self.lines.lock_exclusive()?;
+        let (mut records, superseded) = self.load()?;
+        let existing = records
+            .values()
+            .find(|record| matches(record))
+            .map(Record::id);
+        match existing.and_then(|id| Some((id, records.remove(&id)?))) {
+            Some((id, mut record)) => {
+                change(&mut record);
+                check_id(id, &record)?;
+                let mut changed = self.store(records, superseded, vec![record])?;
+                Ok((changed.remove(0), false))
+            }
+            None => {
+                let record = make(records.keys().next_back().map_or(1, |id| id + 1));
+                self.lines.append_locked(&record)?;
+                Ok((record, true))
+            }
+        }
+    }
+
+    /// Waits for writers in other processes to finish and makes sure everything written
+    /// so far is on disk.
+    pub fn flush(&self) -> io::Result<()> {
+        let _lock = self.lines.lock_exclusive()?;
+        self.lines.sync_locked()
+    }
+
+    /// Appends records that were taken out of `records` and changed, compacting the log if
+    /// it has grown too stale. Must be called with the exclusive lock held.
+    fn store(
+        &self,
+        mut records: BTreeMap<u64, T>,
+        superseded: usize,
+        changed: Vec<T>,
+    ) -> io::Result<Vec<T>> {
+        for record in &changed {
+            self.lines.append_locked(record)?;
+        }
+        if superseded + changed.len() >= COMPACT_THRESHOLD {
+            for record in &changed {
+                records.insert(record.id(), record.clone());
+            }
+            self.lines.rewrite_locked(records.values())?;
+        }
+        Ok(changed)
+    }
+
+    /// Returns the latest snapshot of each record and how many stale snapshots the log holds.
+    fn load(&self) -> io::Result<(BTreeMap<u64, T>, usize)> {
+        let mut records = BTreeMap::new();
+        let mut superseded = 0;
+        for record in self.lines.read_locked()? {
+            if records.insert(record.id(), record).is_some() {
+                superseded += 1;
+            }
+        }
+        Ok((records, superseded))
+    }
+}
+
+fn check_id<T: Record>(id: u64, record: &T) -> io::Result<()> {
+    if record.id() != id {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A record's id cannot be changed",
+        ));
+    }
+    Ok(())
+}
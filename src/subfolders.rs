@@ -0,0 +1,294 @@
+use crate::monitor::create_directory_map;
+use crate::setup::{Config, Course};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A folder inside each course directory, and the words that send a file there.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subfolder {
+    pub name: String,
+    /// Case-insensitive regexes matched against whole words, like `a\d+` or `midterm`.
+    /// A subfolder without cues catches files no other subfolder claims.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cues: Vec<String>,
+}
+
+impl Subfolder {
+    fn new(name: &str, cues: &[&str]) -> Subfolder {
+        Subfolder {
+            name: name.to_string(),
+            cues: cues.iter().map(|cue| cue.to_string()).collect(),
+        }
+    }
+}
+
+pub fn default_subfolders() -> Vec<Subfolder> {
+    vec![
+        Subfolder::new(
+            "Lectures",
+            &[
+                r"lec(ture)?s?\d*",
+                r"l\d{1,2}",
+                "slides?",
+                r"week\s*\d+",
+                "notes",
+            ],
+        ),
+        Subfolder::new(
+            "Assignments",
+            &[
+                r"a\d{1,2}",
+                r"assignments?\s*\d*",
+                r"hw\d*",
+                "homework",
+                r"ps\d+",
+                r"problem\s+sets?",
+            ],
+        ),
+        Subfolder::new("Labs", &[r"labs?\s*\d*"]),
+        Subfolder::new(
+            "Exams",
+            &[
+                "midterms?",
+                "finals?",
+                "exams?",
+                r"quiz(zes)?\s*\d*",
+                "practice",
+            ],
+        ),
+        Subfolder::new("Tutorials", &[r"tut(orial)?s?\s*\d*"]),
+        Subfolder::new("Misc", &[]),
+    ]
+}
+
+struct CompiledSubfolder {
+    name: String,
+    cues: Vec<Regex>,
+}
+
+/// Picks the subfolder of a course directory a file belongs in.
+pub struct Categorizer {
+    default: Vec<CompiledSubfolder>,
+    /// Courses that override the default subfolders.
+    per_course: HashMap<String, Vec<CompiledSubfolder>>,
+}
+
+impl Categorizer {
+    /// Fails if a cue is not a valid regex.
+    pub fn new(config: &Config) -> Result<Categorizer, regex::Error> {
+        let per_course = config
+            .courses
+            .iter()
+            .filter_map(|course| Some((course.name.clone(), course.subfolders.as_ref()?)))
+            .map(|(name, subfolders)| Ok((name, compile(subfolders)?)))
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Categorizer {
+            default: compile(&config.subfolders)?,
+            per_course,
+        })
+    }
+
+    /// The subfolder for a file, judged by its name. Not by its text: nearly every lecture
+    /// mentions notes or practice somewhere. `None` when the course has no subfolders or
+    /// none fits.
+    pub fn categorize(&self, course: &Course, file_name: &str) -> Option<&str> {
+        let subfolders = self.per_course.get(&course.name).unwrap_or(&self.default);
+        let stem = Path::new(file_name)
+            .file_stem()
+            .map(|s| separate_words(&s.to_string_lossy()))
+            .unwrap_or_default();
+        let subfolder = subfolders
+            .iter()
+            .find(|subfolder| subfolder.cues.iter().any(|cue| cue.is_match(&stem)))
+            .or_else(|| {
+                subfolders
+                    .iter()
+                    .find(|subfolder| subfolder.cues.is_empty())
+            })
+            .map(|subfolder| subfolder.name.as_str());
+        subfolder
+    }
+}
+
+fn compile(subfolders: &[Subfolder]) -> Result<Vec<CompiledSubfolder>, regex::Error> {
+    subfolders
+        .iter()
+        .map(|subfolder| {
+            Ok(CompiledSubfolder {
+                name: subfolder.name.clone(),
+                cues: subfolder
+                    .cues
+                    .iter()
+                    .map(|cue| Regex::new(&format!(r"(?i)\b(?:{})\b", cue)))
+                    .collect::<Result<_, _>>()?,
+            })
+        })
+        .collect()
+}
+
+// `\b` treats `_` as part of a word, so `CS246_A3` would never match `a\d+`.
+fn separate_words(text: &str) -> String {
+    text.replace(['_', '-', '.'], " ")
+}
+
+/// Creates every course directory for the current term and the subfolders inside it.
+pub fn scaffold(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let directory_map = create_directory_map(config)?;
+    for course in &config.courses {
+        let Some(course_path) = directory_map.get(&course.name) else {
+            continue;
+        };
+        fs::create_dir_all(course_path)?;
+        for subfolder in course.subfolders.as_ref().unwrap_or(&config.subfolders) {
+            fs::create_dir_all(course_path.join(&subfolder.name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    /// A config for the term `2A` under `dir` with the given `courses` and extra settings.
+    fn config(dir: &Path, courses: &str, extra: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            "
+download_path: {dir}/Downloads
+base_path: {dir}/School
+current_term: 2A
+start_year: 2024
+end_year: 2029
+coop: true
+api_key: ''
+courses:
+{courses}
+{extra}
+",
+            dir = dir.display()
+        ))
+        .unwrap()
+    }
+
+    fn course(name: &str) -> Course {
+        Course {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn with_defaults(dir: &Path) -> Config {
+        let mut config = config(dir, "- { name: CS246, description: OOP }", "");
+        config.subfolders = default_subfolders();
+        config
+    }
+
+    #[test]
+    fn first_subfolder_with_a_matching_cue_wins() {
+        let config = with_defaults(Path::new("/home/user"));
+        let categorizer = Categorizer::new(&config).unwrap();
+        let cases = [
+            // Lectures come before Exams, so a lecture named "final" stays a lecture.
+            ("lec_05_final.pdf", Some("Lectures")),
+            ("L05.pdf", Some("Lectures")),
+            ("week 3 slides.pptx", Some("Lectures")),
+            ("CS246_A3.pdf", Some("Assignments")),
+            ("cs246-hw2.pdf", Some("Assignments")),
+            ("Lab 4.pdf", Some("Labs")),
+            ("midterm_practice.pdf", Some("Exams")),
+            ("Final Exam 2019.pdf", Some("Exams")),
+            ("tut07.pdf", Some("Tutorials")),
+            ("syllabus.pdf", Some("Misc")),
+            // Cues match whole words of the name, not its extension.
+            ("translate.notes", Some("Misc")),
+            ("alabaster.pdf", Some("Misc")),
+        ];
+        for (file_name, expected) in cases {
+            assert_eq!(
+                categorizer.categorize(&course("CS246"), file_name),
+                expected,
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn courses_can_override_the_subfolders() {
+        let mut config = config(
+            Path::new("/home/user"),
+            "
+- { name: CS246, description: OOP }
+- name: PD1
+  description: Professional Development
+  subfolders: [{ name: Reflections, cues: [reflection] }, { name: Other }]
+- { name: MATH239, description: Combinatorics, subfolders: [] }",
+            "",
+        );
+        config.subfolders = default_subfolders();
+        let categorizer = Categorizer::new(&config).unwrap();
+        let cases = [
+            ("CS246", "lec_05_final.pdf", Some("Lectures")),
+            ("PD1", "reflection_2.pdf", Some("Reflections")),
+            ("PD1", "lec_05_final.pdf", Some("Other")),
+            ("MATH239", "lec_05_final.pdf", None),
+        ];
+        for (name, file_name, expected) in cases {
+            assert_eq!(
+                categorizer.categorize(&course(name), file_name),
+                expected,
+                "{} {}",
+                name,
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn without_a_subfolders_list_course_folders_stay_flat() {
+        let dir = temp_dir("subfolders-flat");
+        let config = config(&dir, "- { name: CS246, description: OOP }", "");
+        assert!(config.subfolders.is_empty());
+        let categorizer = Categorizer::new(&config).unwrap();
+        assert_eq!(
+            categorizer.categorize(&course("CS246"), "CS246_A3.pdf"),
+            None
+        );
+
+        scaffold(&config).unwrap();
+        let course_dir = dir.join("School").join("2A").join("CS246");
+        assert!(course_dir.is_dir());
+        assert_eq!(fs::read_dir(&course_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn scaffold_creates_the_subfolders() {
+        let dir = temp_dir("subfolders-scaffold");
+        scaffold(&with_defaults(&dir)).unwrap();
+        let course_dir = dir.join("School").join("2A").join("CS246");
+        for subfolder in [
+            "Lectures",
+            "Assignments",
+            "Labs",
+            "Exams",
+            "Tutorials",
+            "Misc",
+        ] {
+            assert!(course_dir.join(subfolder).is_dir(), "{}", subfolder);
+        }
+    }
+
+    #[test]
+    fn invalid_cue_is_an_error() {
+        let config = config(
+            Path::new("/home/user"),
+            "- { name: CS246, description: OOP }",
+            "subfolders: [{ name: Lectures, cues: ['lec(' ] }]",
+        );
+        assert!(Categorizer::new(&config).is_err());
+    }
+}
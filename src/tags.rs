@@ -0,0 +1,63 @@
+use plist::Value;
+use std::io;
+use std::path::Path;
+
+/// Finder tags on macOS: a binary plist array of tag names.
+const MACOS_TAGS_ATTRIBUTE: &str = "com.apple.metadata:_kMDItemUserTags";
+/// The freedesktop convention: a comma-separated list.
+const XDG_TAGS_ATTRIBUTE: &str = "user.xdg.tags";
+
+/// Adds `tags` to the file's tags, keeping the ones it already has.
+pub fn tag_file(path: &Path, tags: &[String]) -> io::Result<()> {
+    let mut all = read_tags(path)?;
+    for tag in tags {
+        if !all.contains(tag) {
+            all.push(tag.clone());
+        }
+    }
+    write_tags(path, &all)
+}
+
+pub fn read_tags(path: &Path) -> io::Result<Vec<String>> {
+    if cfg!(target_os = "macos") {
+        let Some(data) = xattr::get(path, MACOS_TAGS_ATTRIBUTE)? else {
+            return Ok(Vec::new());
+        };
+        let value = Value::from_reader(io::Cursor::new(data)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse plist: {}", e),
+            )
+        })?;
+        Ok(match value {
+            Value::Array(array) => array
+                .into_iter()
+                .filter_map(|item| item.into_string())
+                .collect(),
+            _ => Vec::new(),
+        })
+    } else {
+        let Some(data) = xattr::get(path, XDG_TAGS_ATTRIBUTE)? else {
+            return Ok(Vec::new());
+        };
+        Ok(String::from_utf8_lossy(&data)
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+fn write_tags(path: &Path, tags: &[String]) -> io::Result<()> {
+    if cfg!(target_os = "macos") {
+        let value = Value::Array(tags.iter().cloned().map(Value::String).collect());
+        let mut data = Vec::new();
+        value
+            .to_writer_binary(&mut data)
+            .map_err(io::Error::other)?;
+        xattr::set(path, MACOS_TAGS_ATTRIBUTE, &data)
+    } else {
+        xattr::set(path, XDG_TAGS_ATTRIBUTE, tags.join(",").as_bytes())
+    }
+}
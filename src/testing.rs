@@ -0,0 +1,13 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh, empty directory for one test. Tests run in parallel, so `name` must be
+/// unique across the crate, e.g. `executor-suffix`.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nimbus-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}